@@ -0,0 +1,124 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use tes3::nif::NiStream;
+
+use crate::cli::{Args, Projectile};
+use crate::pincushion::apply_pincushion;
+
+/// The time spent in each stage of a single pass over one file.
+struct Sample {
+    file: usize,
+    parse: Duration,
+    transform: Duration,
+    serialize: Duration,
+}
+
+impl Sample {
+    fn total(&self) -> Duration {
+        self.parse + self.transform + self.serialize
+    }
+}
+
+/// Process every NIF file in `dir` the given number of times and report how
+/// long each stage took. Files are read into memory up front and nothing is
+/// written, so the timings exclude disk access.
+///
+pub fn bench(args: &Args, dir: &Path, iterations: usize, projectile: Projectile) {
+    let mut files = vec![];
+    crate::find_nif_files(dir, &mut files);
+
+    let params = args.params(projectile);
+
+    let mut samples = vec![];
+    let mut total_bytes = 0;
+
+    for (file, path) in files.iter().enumerate() {
+        let Ok(bytes) = std::fs::read(path) else {
+            warn!("Failed to read file: {path:?}");
+            continue;
+        };
+
+        let key = path.to_string_lossy();
+
+        for _ in 0..iterations {
+            let start = Instant::now();
+            let mut stream = NiStream::default();
+            if stream.load_bytes(&bytes).is_err() {
+                warn!("Failed to parse NIF file: {path:?}");
+                break;
+            }
+            let parse = start.elapsed();
+
+            if stream.roots.len() != 1 {
+                warn!("Invalid root node count: {path:?}");
+                break;
+            }
+
+            let start = Instant::now();
+            apply_pincushion(&mut stream, args, &params, &key);
+            let transform = start.elapsed();
+
+            let start = Instant::now();
+            if stream.save_bytes().is_err() {
+                warn!("Failed to serialize NIF file: {path:?}");
+                break;
+            }
+            let serialize = start.elapsed();
+
+            total_bytes += bytes.len();
+            samples.push(Sample {
+                file,
+                parse,
+                transform,
+                serialize,
+            });
+        }
+    }
+
+    if samples.is_empty() {
+        println!("No NIF files were processed in: {dir:?}");
+        return;
+    }
+
+    println!("{} files, {} passes", files.len(), samples.len());
+    print_stage("parse", samples.iter().map(|s| s.parse));
+    print_stage("transform", samples.iter().map(|s| s.transform));
+    print_stage("serialize", samples.iter().map(|s| s.serialize));
+    print_stage("total", samples.iter().map(Sample::total));
+
+    let elapsed: Duration = samples.iter().map(Sample::total).sum();
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "Throughput: {:.1} passes/s, {:.2} MiB/s",
+        samples.len() as f64 / seconds,
+        total_bytes as f64 / (1024.0 * 1024.0) / seconds,
+    );
+
+    // Outliers are easier to spot by file than by percentile.
+    let mut slowest: Vec<_> = samples.iter().collect();
+    slowest.sort_by_key(|s| std::cmp::Reverse(s.total()));
+    slowest.dedup_by_key(|s| s.file);
+
+    println!("Slowest files:");
+    for sample in slowest.iter().take(5) {
+        println!("{:>10.3?}  {:?}", sample.total(), files[sample.file]);
+    }
+}
+
+fn print_stage(name: &str, durations: impl Iterator<Item = Duration>) {
+    let mut durations: Vec<_> = durations.collect();
+    durations.sort();
+
+    let percentile = |p: f64| durations[((durations.len() - 1) as f64 * p).round() as usize];
+
+    println!(
+        "{name:>10}: p50 {:>10.3?}  p90 {:>10.3?}  p99 {:>10.3?}  max {:>10.3?}",
+        percentile(0.50),
+        percentile(0.90),
+        percentile(0.99),
+        durations[durations.len() - 1],
+    );
+}
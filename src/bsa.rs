@@ -0,0 +1,323 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+/// The version field of a Morrowind BSA header.
+const VERSION: u32 = 0x100;
+
+/// The file index of a Morrowind BSA archive.
+///
+/// Only the index is read up front, file data is read on demand.
+///
+pub struct Archive {
+    path: PathBuf,
+    /// Size and absolute offset of each file, by normalized path.
+    files: HashMap<String, (u32, u64)>,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+
+        let mut file = std::fs::File::open(path)?;
+
+        let mut header = [0; 12];
+        file.read_exact(&mut header)?;
+        let [version, hash_offset, count] =
+            [0, 4, 8].map(|i| u32::from_le_bytes(header[i..i + 4].try_into().unwrap()));
+
+        if version != VERSION {
+            return Err(invalid("not a Morrowind BSA"));
+        }
+
+        // Checked before anything is allocated, a corrupt header could ask for
+        // gigabytes. The index and hash table must both fit in the file.
+        let (index_len, table_len) = (u64::from(hash_offset), u64::from(count) * 8);
+        if u64::from(count) * 12 > index_len || 12 + index_len + table_len > file.metadata()?.len()
+        {
+            return Err(invalid("file index out of bounds"));
+        }
+        let (hash_offset, count) = (hash_offset as usize, count as usize);
+
+        // File records and name offsets, followed by the names themselves.
+        let names_start = count * 12;
+        let mut index = vec![0; hash_offset];
+        file.read_exact(&mut index)?;
+
+        // File data follows the hash table, after the header and index.
+        let data_start = (12 + hash_offset + count * 8) as u64;
+
+        let read_u32 = |at: usize| u32::from_le_bytes(index[at..at + 4].try_into().unwrap());
+        let mut files = HashMap::with_capacity(count);
+
+        for i in 0..count {
+            let size = read_u32(i * 8);
+            let offset = read_u32(i * 8 + 4);
+            let name_start = names_start + read_u32(count * 8 + i * 4) as usize;
+            let name = index
+                .get(name_start..)
+                .and_then(|names| names.split(|&c| c == 0).next())
+                .ok_or_else(|| invalid("file name out of bounds"))?;
+            let name = normalize(&String::from_utf8_lossy(name));
+            files.insert(name, (size, data_start + u64::from(offset)));
+        }
+
+        Ok(Self {
+            path: path.canonicalize()?,
+            files,
+        })
+    }
+
+    /// Read a file, given its path from the root of the archive.
+    pub fn read(&self, name: &str) -> Option<std::io::Result<Vec<u8>>> {
+        let &(size, offset) = self.files.get(&normalize(name))?;
+        Some(read_at(&self.path, offset, size as usize))
+    }
+}
+
+/// The archives of the load order, see `fallback-archive=` in openmw.cfg.
+///
+/// Files are extracted on request, so they can be processed like loose files.
+/// They go to a private temporary directory that is created on first use and
+/// removed once the archives are dropped. Archives listed later override
+/// earlier ones, as in the engine.
+///
+pub struct Archives {
+    archives: Vec<Archive>,
+    cache: OnceCell<Option<PathBuf>>,
+}
+
+impl Archives {
+    /// Open the named archives, found in the last data directory having them.
+    pub fn open(names: &[String], data_directories: &[PathBuf]) -> Self {
+        let mut archives = vec![];
+        for name in names {
+            let Some(path) = data_directories
+                .iter()
+                .map(|dir| dir.join(name))
+                .rfind(|path| path.is_file())
+            else {
+                warn!("Archive not found in any data directory: {name}");
+                continue;
+            };
+            match Archive::open(&path) {
+                Ok(archive) => archives.push(archive),
+                Err(error) => warn!("Failed to read archive {path:?}: {error}"),
+            }
+        }
+        Self {
+            archives,
+            cache: OnceCell::new(),
+        }
+    }
+
+    /// Whether `path` is one of the opened archives.
+    pub fn contains(&self, path: &Path) -> bool {
+        let Ok(path) = path.canonicalize() else {
+            return false;
+        };
+        self.archives.iter().any(|archive| archive.path == path)
+    }
+
+    /// The path of the archive with the highest priority having a file.
+    pub fn find(&self, name: &str) -> Option<&Path> {
+        self.archive_of(name).map(|archive| archive.path.as_path())
+    }
+
+    fn archive_of(&self, name: &str) -> Option<&Archive> {
+        self.archives
+            .iter()
+            .rfind(|archive| archive.files.contains_key(&normalize(name)))
+    }
+
+    /// Extract a file from the archive with the highest priority having it.
+    ///
+    /// Returns the path it was extracted to, under the archive's file name.
+    ///
+    pub fn extract(&self, name: &str) -> Option<PathBuf> {
+        // The name comes from a record, it mustn't point outside the cache.
+        if name.split(['/', '\\']).any(|part| part == "..") {
+            return None;
+        }
+
+        let archive = self.archive_of(name)?;
+
+        let cache = self.cache.get_or_init(|| {
+            create_private_dir()
+                .inspect_err(|error| warn!("Failed to create a directory to extract to: {error}"))
+                .ok()
+        });
+
+        let file_name = archive.path.file_name().unwrap_or_default();
+        let path = cache.as_ref()?.join(file_name).join(normalize(name));
+
+        let result = archive.read(name)?.and_then(|bytes| {
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(&path, bytes)
+        });
+
+        match result {
+            Ok(()) => {
+                debug!("Extracted {name} from {:?}", archive.path);
+                Some(path)
+            }
+            Err(error) => {
+                warn!("Failed to extract {name} from {:?}: {error}", archive.path);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Archives {
+    fn drop(&mut self) {
+        if let Some(Some(dir)) = self.cache.get()
+            && let Err(error) = std::fs::remove_dir_all(dir)
+        {
+            warn!("Failed to remove extracted files {dir:?}: {error}");
+        }
+    }
+}
+
+/// Create a new directory in the system's temporary directory, only
+/// accessible to the current user.
+///
+/// Every run gets a directory of its own, an existing one is never reused, so
+/// nothing left there by other runs or other users is followed or written to.
+///
+fn create_private_dir() -> std::io::Result<PathBuf> {
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+    let prefix = format!("{}-{}", env!("CARGO_PKG_NAME"), std::process::id());
+    let mut attempt = 0;
+    loop {
+        let path = std::env::temp_dir().join(format!("{prefix}-{attempt}"));
+        match builder.create(&path) {
+            Ok(()) => return Ok(path),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn read_at(path: &Path, offset: u64, size: usize) -> std::io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = vec![0; size];
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Lowercase and use forward slashes, archives store backslashes.
+fn normalize(name: &str) -> String {
+    name.replace('\\', "/").to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Morrowind BSA holding the given files.
+    fn bsa(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut records = Vec::<u8>::new();
+        let mut name_offsets = Vec::<u8>::new();
+        let mut names = Vec::<u8>::new();
+        let mut data = Vec::<u8>::new();
+        for (name, contents) in files {
+            records.extend((contents.len() as u32).to_le_bytes());
+            records.extend((data.len() as u32).to_le_bytes());
+            name_offsets.extend((names.len() as u32).to_le_bytes());
+            names.extend(name.as_bytes());
+            names.push(0);
+            data.extend(contents.as_bytes());
+        }
+
+        let hash_offset = records.len() + name_offsets.len() + names.len();
+        let mut bsa = vec![];
+        for value in [VERSION, hash_offset as u32, files.len() as u32] {
+            bsa.extend(value.to_le_bytes());
+        }
+        bsa.extend(records);
+        bsa.extend(name_offsets);
+        bsa.extend(names);
+        bsa.extend(vec![0; files.len() * 8]);
+        bsa.extend(data);
+        bsa
+    }
+
+    /// Open a single archive with the given contents.
+    fn archives(dir: &Path, bytes: &[u8]) -> Archives {
+        std::fs::write(dir.join("Test.bsa"), bytes).unwrap();
+        Archives::open(&["Test.bsa".into()], &[dir.to_path_buf()])
+    }
+
+    #[test]
+    fn archived_files_are_extracted() {
+        let dir = crate::fixtures::temp_dir("bsa-extract");
+        let archives = archives(
+            &dir,
+            &bsa(&[(r"meshes\w\a.nif", "arrow"), (r"meshes\w\b.nif", "bolt")]),
+        );
+
+        assert_eq!(
+            archives.find("Meshes/W/B.nif"),
+            Some(dir.join("Test.bsa").canonicalize().unwrap().as_path())
+        );
+        let path = archives.extract("meshes/w/b.nif").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"bolt");
+        assert!(archives.extract("meshes/w/c.nif").is_none());
+
+        drop(archives);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn names_leaving_the_cache_are_not_extracted() {
+        let dir = crate::fixtures::temp_dir("bsa-parent-dir");
+        let archives = archives(&dir, &bsa(&[(r"meshes\..\..\a.nif", "arrow")]));
+
+        assert!(archives.find("meshes/../../a.nif").is_some());
+        assert!(archives.extract("meshes/../../a.nif").is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_headers_are_rejected() {
+        let dir = crate::fixtures::temp_dir("bsa-corrupt");
+        let path = dir.join("Corrupt.bsa");
+        let mut bytes = bsa(&[(r"meshes\w\a.nif", "arrow")]);
+        bytes[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let error = Archive::open(&path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn private_dirs_are_never_shared() {
+        let first = create_private_dir().unwrap();
+        let second = create_private_dir().unwrap();
+
+        assert_ne!(first, second);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&first).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        std::fs::remove_dir(first).unwrap();
+        std::fs::remove_dir(second).unwrap();
+    }
+}
@@ -0,0 +1,1014 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+
+use tes3::esp::{WeaponData, WeaponType};
+use tes3::nif::Vec3;
+
+use crate::overrides::{Overrides, parse_overrides};
+use crate::pincushion::{DEFAULT_ROOT_FLAGS, parse_nif_version};
+use crate::profile::{Profiles, parse_profiles};
+use crate::report;
+
+/// Default directory that generated meshes are written to.
+const OUTPUT_DIR: &str = "openmw_pincushion_generator";
+
+/// Values used by `--plant` for anything not given explicitly.
+const PLANT_ARROW_OFFSET: &str = "12";
+const PLANT_BOLT_OFFSET: &str = "8";
+const PLANT_SCALE: &str = "0.8";
+
+/// Values used by `--auto` for anything not given explicitly, the offset is a
+/// fraction of the mesh's length.
+const AUTO_OFFSET: &str = "0.25";
+const AUTO_SCALE: &str = "1";
+
+/// Generate pincushion projectile NIFs for OpenMW
+#[derive(Parser, Debug)]
+#[command(version, about, subcommand_negates_reqs = true)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arrow offset
+    #[arg(
+        long,
+        required_unless_present_any = ["plant", "auto"],
+        default_value_ifs([("auto", "true", AUTO_OFFSET), ("plant", "true", PLANT_ARROW_OFFSET)])
+    )]
+    pub arrow_offset: Option<f32>,
+
+    /// Arrow scale
+    #[arg(
+        long,
+        required_unless_present_any = ["plant", "auto"],
+        default_value_ifs([("plant", "true", PLANT_SCALE), ("auto", "true", AUTO_SCALE)])
+    )]
+    pub arrow_scale: Option<f32>,
+
+    /// Bolt offset
+    #[arg(
+        long,
+        required_unless_present_any = ["plant", "auto"],
+        default_value_ifs([("auto", "true", AUTO_OFFSET), ("plant", "true", PLANT_BOLT_OFFSET)])
+    )]
+    pub bolt_offset: Option<f32>,
+
+    /// Bolt scale
+    #[arg(
+        long,
+        required_unless_present_any = ["plant", "auto"],
+        default_value_ifs([("plant", "true", PLANT_SCALE), ("auto", "true", AUTO_SCALE)])
+    )]
+    pub bolt_scale: Option<f32>,
+
+    /// Preset for arrows and bolts that look planted in whatever they hit
+    ///
+    /// Sinks the tip into the surface and shrinks the shaft towards it, with
+    /// collision disabled as usual. The offsets, scales and --scale-pivot can
+    /// still be given to override the preset.
+    #[arg(long)]
+    pub plant: bool,
+
+    /// Throwable offset
+    #[arg(long, default_value_t = 0.0)]
+    pub throwable_offset: f32,
+
+    /// Process throwables exactly like arrows, with the arrow offset and scale
+    ///
+    /// They are treated as arrows throughout, including by --no-nc-for.
+    #[arg(long)]
+    pub thrown_as_arrow: bool,
+
+    /// Only flip throwables whose geometry isn't mirrored already
+    #[arg(long)]
+    pub auto_flip: bool,
+
+    /// Size the transform to each mesh from its length along the axis
+    ///
+    /// The offsets are read as a fraction of the mesh's length once scaled,
+    /// e.g. 0.25 sinks a quarter of the shaft. Arrows and bolts are also
+    /// scaled to a common length first, see --auto-length, so projectiles of
+    /// unusual size come out like the rest. The offsets and scales default to
+    /// 0.25 and 1.
+    #[arg(long)]
+    pub auto: bool,
+
+    /// The length that --auto scales arrows and bolts to, before their scale
+    ///
+    /// Defaults to a typical length for each, 60 units for arrows and 40 for
+    /// bolts.
+    #[arg(long, value_name = "UNITS", requires = "auto")]
+    pub auto_length: Option<f32>,
+
+    /// The point that scales are applied about
+    #[arg(long, value_enum, default_value_t = ScalePivot::Origin, default_value_if("plant", "true", "tip"))]
+    pub scale_pivot: ScalePivot,
+
+    /// Derive offsets from this weapon field instead of the offset arguments (advanced)
+    ///
+    /// Experimental, for load orders that encode stick depth in a weapon's
+    /// data by convention. The offset is the field's value times
+    /// --offset-field-scale.
+    #[arg(long, value_enum, value_name = "FIELD")]
+    pub offset_from_field: Option<OffsetField>,
+
+    /// Factor applied to the field given by --offset-from-field (advanced)
+    #[arg(long, default_value_t = 1.0, requires = "offset_from_field")]
+    pub offset_field_scale: f32,
+
+    /// Axis that arrow offsets are applied along
+    #[arg(long, value_enum, default_value_t = Axis::Y)]
+    pub arrow_axis: Axis,
+
+    /// Axis that bolt offsets are applied along
+    #[arg(long, value_enum, default_value_t = Axis::Y)]
+    pub bolt_axis: Axis,
+
+    /// Axis that throwable offsets are applied along
+    #[arg(long, value_enum, default_value_t = Axis::Y)]
+    pub throwable_axis: Axis,
+
+    /// What to do with meshes whose root isn't a node, e.g. a lone NiTriShape
+    #[arg(long, value_enum, default_value_t = NonNodeRoot::Wrap)]
+    pub non_node_root: NonNodeRoot,
+
+    /// When to wrap the root of arrow meshes in a new node
+    #[arg(long, value_enum, default_value_t = WrapMode::Auto)]
+    pub arrow_wrap: WrapMode,
+
+    /// When to wrap the root of bolt meshes in a new node
+    #[arg(long, value_enum, default_value_t = WrapMode::Auto)]
+    pub bolt_wrap: WrapMode,
+
+    /// When to wrap the root of throwable meshes in a new node
+    #[arg(long, value_enum, default_value_t = WrapMode::Auto)]
+    pub throwable_wrap: WrapMode,
+
+    /// Additional per-axis arrow scale, baked into the geometry (x,y,z)
+    #[arg(long, value_parser = parse_vec3)]
+    pub arrow_scale_vec: Option<Vec3>,
+
+    /// Additional per-axis bolt scale, baked into the geometry (x,y,z)
+    #[arg(long, value_parser = parse_vec3)]
+    pub bolt_scale_vec: Option<Vec3>,
+
+    /// Rotate each mesh randomly by up to this many degrees
+    ///
+    /// This gives variety between different meshes, not between hits, as the
+    /// rotation is baked into the generated files. Each projectile type draws
+    /// its own rotation from the --seed.
+    #[arg(long, value_name = "DEGREES")]
+    pub jitter_rotation: Option<f32>,
+
+    /// Arrow jitter in degrees, overrides --jitter-rotation for arrows
+    #[arg(long, value_name = "DEGREES")]
+    pub arrow_jitter: Option<f32>,
+
+    /// Bolt jitter in degrees, overrides --jitter-rotation for bolts
+    #[arg(long, value_name = "DEGREES")]
+    pub bolt_jitter: Option<f32>,
+
+    /// Throwable jitter in degrees, overrides --jitter-rotation for throwables
+    #[arg(long, value_name = "DEGREES")]
+    pub throwable_jitter: Option<f32>,
+
+    /// Round transformed values to this many decimals, for cleaner diffs
+    ///
+    /// This is purely cosmetic. At least 3 decimals are kept, so the change is
+    /// far below anything visible.
+    #[arg(long, value_name = "DECIMALS", value_parser = clap::value_parser!(u8).range(3..=9))]
+    pub round_transforms: Option<u8>,
+
+    /// Seed for the random jitter, the same seed gives the same output
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Transform the node with this name instead of the root's children (advanced)
+    #[arg(long, value_name = "NAME")]
+    pub root_name: Option<String>,
+
+    /// Replace LOD nodes with their most detailed level
+    #[arg(long)]
+    pub strip_lod: bool,
+
+    /// Only transform nodes with geometry below them, leaving helpers in place
+    ///
+    /// Lights, particle emitters and similar nodes without any geometry keep
+    /// their original transform.
+    #[arg(long)]
+    pub geometry_only: bool,
+
+    /// Only transform root children with these names (comma separated)
+    ///
+    /// Names are matched case insensitively, other children keep their
+    /// original transform. All children are transformed if none are given.
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    pub transform_nodes: Vec<String>,
+
+    /// Bake the scale into the vertices of skinned meshes (advanced)
+    ///
+    /// Skinned geometry may not follow the scale of its node. Bone bind poses
+    /// are left as they are.
+    #[arg(long)]
+    pub bake_skinned_scale: bool,
+
+    /// Offset so that the node with this name reaches the offset (advanced)
+    ///
+    /// Falls back to offsetting the whole mesh if no such node exists.
+    #[arg(long, value_name = "NAME")]
+    pub anchor_node: Option<String>,
+
+    /// Flags for inserted root nodes (advanced)
+    #[arg(long, default_value_t = DEFAULT_ROOT_FLAGS, value_parser = parse_flags)]
+    pub root_flags: u16,
+
+    /// How collision is disabled on generated meshes
+    #[arg(long, value_enum, default_value_t = NcMethod::String)]
+    pub nc_method: NcMethod,
+
+    /// Where the "NC" string extra data goes in the root node's extra data
+    #[arg(long, value_enum, default_value_t = NcPosition::Front)]
+    pub nc_position: NcPosition,
+
+    /// Projectile types that keep their collision (comma separated)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub no_nc_for: Vec<Projectile>,
+
+    /// Remove objects that are unreachable from the root before saving
+    #[arg(long)]
+    pub optimize: bool,
+
+    /// Keep running and reprocess meshes when the data directories change
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Only print how many meshes would be generated, without reading any
+    #[arg(long)]
+    pub count_only: bool,
+
+    /// Print the content files in load order, with the path each resolved to
+    #[arg(long)]
+    pub echo_load_order: bool,
+
+    /// Check the data directories and content files of the OpenMW config, then exit
+    ///
+    /// Exits with status 1 if a directory or content file is missing or
+    /// unreadable, or if no data directory is usable at all.
+    #[arg(long)]
+    pub validate_config: bool,
+
+    /// Skip source meshes older than this NIF version, e.g. 4.0.0.2
+    #[arg(long, value_name = "VERSION", value_parser = parse_nif_version)]
+    pub min_nif_version: Option<u32>,
+
+    /// Only generate meshes whose output file doesn't exist yet
+    ///
+    /// Existing files are kept whatever their content, e.g. to resume a run
+    /// that was interrupted.
+    #[arg(long)]
+    pub only_missing: bool,
+
+    /// Skip meshes whose output was generated from the same source and arguments
+    ///
+    /// Generated meshes record a hash of their source and of the arguments
+    /// used, so this works regardless of file times.
+    #[arg(long)]
+    pub skip_unchanged: bool,
+
+    /// Process this one NIF file instead of the meshes of the load order
+    ///
+    /// The OpenMW configuration isn't read, the file is processed as the type
+    /// given by --input-type.
+    #[arg(long, value_name = "PATH")]
+    pub input_nif: Option<PathBuf>,
+
+    /// Projectile type to process --input-nif as, or to save --save-template for
+    #[arg(long, value_enum, default_value_t = Projectile::Arrow)]
+    pub input_type: Projectile,
+
+    /// Save the transform for --input-type as a minimal NIF and exit
+    ///
+    /// The file holds a root with a single empty child, transformed as the
+    /// child of a real mesh would be. Pass it to `extract-transform` to get
+    /// the arguments back.
+    #[arg(long, value_name = "PATH", conflicts_with = "input_nif")]
+    pub save_template: Option<PathBuf>,
+
+    /// Write the processed --input-nif to stdout instead of the output directory
+    ///
+    /// All log messages go to stderr, so the output can be piped.
+    #[arg(long, requires = "input_nif")]
+    pub stdout: bool,
+
+    /// Tune the offset and scale on --input-nif, then process the load order
+    ///
+    /// The mesh is processed and saved to the output directory, and new
+    /// values are prompted for until accepted with an empty line. The whole
+    /// batch is then processed with the accepted values. Without a terminal
+    /// on stdin the given values are used as they are.
+    #[arg(long, requires = "input_nif", conflicts_with = "stdout")]
+    pub interactive: bool,
+
+    /// Go through the whole run without writing any meshes
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Write the planned operations of a dry run to this JSON file
+    ///
+    /// Lists every mesh with its source, output, transform and the decisions
+    /// made for it, exactly as a real run would.
+    #[arg(long, value_name = "PATH", requires = "dry_run")]
+    pub plan: Option<PathBuf>,
+
+    /// Process meshes that were generated by a previous run again
+    #[arg(long)]
+    pub reprocess: bool,
+
+    /// Write a manifest of all processed meshes
+    ///
+    /// The format follows the extension, which must be .json, .csv or .toml.
+    #[arg(long, value_name = "PATH", value_parser = parse_manifest_path)]
+    pub manifest: Option<PathBuf>,
+
+    /// Merge into an existing manifest instead of replacing it
+    #[arg(long, requires = "manifest")]
+    pub manifest_append: bool,
+
+    /// Only regenerate meshes whose weapon record changed since the last run
+    ///
+    /// Records are compared against the hashes stored in the --manifest of
+    /// the previous run. Outputs are kept only while the hash recorded in
+    /// them still matches too, so changed arguments or meshes regenerate as
+    /// they would with --skip-unchanged.
+    #[arg(long, requires = "manifest")]
+    pub only_changed_records: bool,
+
+    /// List meshes that were saved without any meaningful transform
+    #[arg(long)]
+    pub report_unprocessed: bool,
+
+    /// How to resolve weapons whose mesh or type differs between plugins
+    ///
+    /// These are usually distinct weapons sharing an id by mistake. Each one
+    /// is warned about with the plugins involved and the definition used.
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::LastWins)]
+    pub conflict_policy: ConflictPolicy,
+
+    /// Only process the weapon ids listed in this file, one per line
+    ///
+    /// Listed ids are processed even if they look like spell VFX. Matching is
+    /// case-insensitive, and lines starting with `#` are ignored.
+    #[arg(long, value_name = "PATH", value_parser = parse_id_list)]
+    pub id_list: Option<HashSet<String>>,
+
+    /// Per-mesh and per-weapon offsets and scales from this TOML file
+    ///
+    /// Meshes are listed as `[meshes."w/w_arrow_odd.nif"]`, relative to the
+    /// meshes directory, and weapons as `[weapons.my_broken_bolt]` by their
+    /// id. Both are matched case insensitively, and a weapon's entry wins over
+    /// its mesh's.
+    ///
+    /// Each entry may set `offset = 4.0` to replace the offset argument of its
+    /// type, `scale = 0.9` to replace the scale argument (throwables keep their
+    /// flip), and `skip = true` to leave the mesh or weapon out entirely.
+    #[arg(long, value_name = "PATH", value_parser = parse_overrides)]
+    pub overrides: Option<Overrides>,
+
+    /// Emit one variant per profile defined in this TOML file
+    #[arg(long, value_name = "PATH", value_parser = parse_profiles)]
+    pub profiles: Option<Profiles>,
+
+    /// Run this command on each generated mesh, `{path}` is replaced with its path
+    ///
+    /// The command is split on whitespace and run without a shell, so paths
+    /// containing spaces are passed as a single argument.
+    #[arg(long, value_name = "COMMAND")]
+    pub post_hook: Option<String>,
+
+    /// Render a thumbnail of each generated mesh and write an HTML index here
+    ///
+    /// Thumbnails mirror the layout of the output directory, with a ".png"
+    /// extension. Meshes that fail to render are listed without one.
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "gallery_renderer",
+        conflicts_with = "dry_run"
+    )]
+    pub gallery: Option<PathBuf>,
+
+    /// Command rendering a thumbnail for --gallery, see below
+    ///
+    /// `{path}` is replaced with the path of the mesh and `{thumbnail}` with
+    /// the path of the image to write. The command is run like --post-hook.
+    #[arg(long, value_name = "COMMAND", requires = "gallery")]
+    pub gallery_renderer: Option<String>,
+
+    /// Name outputs after this template instead of the original mesh path
+    ///
+    /// Supports {type}, {id}, {basename} and {dir}, e.g. "{type}/{id}_{basename}.nif".
+    /// Paths are relative to the output directory.
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_output_template)]
+    pub output_template: Option<OutputTemplate>,
+
+    /// Keep an untouched copy of each mesh and add this suffix to the stuck one
+    #[arg(long, value_name = "SUFFIX")]
+    pub stuck_suffix: Option<String>,
+
+    /// Treat warnings and unprocessed meshes as failures
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Print the openmw.cfg lines needed to load the generated meshes
+    #[arg(long)]
+    pub emit_cfg_snippet: bool,
+
+    /// Add the output directory as a data= entry to openmw.cfg, see --openmw-cfg
+    ///
+    /// The entry goes last, so the generated meshes override the originals.
+    /// Nothing is changed if it's there already, otherwise a copy of the
+    /// previous file is kept as openmw.cfg.bak.
+    #[arg(long, conflicts_with = "dry_run")]
+    pub register: bool,
+
+    /// Directory that generated meshes are written to
+    #[arg(long, value_name = "PATH", default_value = OUTPUT_DIR)]
+    pub output_dir: PathBuf,
+
+    /// The openmw.cfg to read, or the directory containing it
+    ///
+    /// Defaults to the user's configuration in its usual location. Useful
+    /// for portable installs, multiple profiles or Flatpak layouts.
+    #[arg(long, value_name = "PATH", env = "OPENMW_CONFIG", value_parser = parse_openmw_cfg)]
+    pub openmw_cfg: Option<PathBuf>,
+
+    /// Directory that a relative --output-dir is resolved against
+    ///
+    /// Defaults to the current working directory.
+    #[arg(long, value_name = "PATH")]
+    pub base_dir: Option<PathBuf>,
+
+    /// Write every projectile mesh with the weapons that use it to this file
+    ///
+    /// Written as JSON if the path ends in .json, as a plain text list
+    /// otherwise.
+    #[arg(long, value_name = "PATH")]
+    pub shared_mesh_report: Option<PathBuf>,
+
+    /// Write the bounding box of each generated mesh to this JSON file
+    ///
+    /// Bounds are in the space of the mesh's root, after transforming, e.g. for
+    /// authoring a custom collision shape.
+    #[arg(long, value_name = "PATH")]
+    pub export_bounds: Option<PathBuf>,
+
+    /// Write the node tree of each mesh before and after processing to this directory
+    #[arg(long, value_name = "DIR")]
+    pub report_graph: Option<PathBuf>,
+
+    /// Write every log message to this file, whatever the verbosity
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Only print a single summary line, and errors
+    ///
+    /// The line has the stable format `processed=<n> skipped=<n> failed=<n>`.
+    /// The exit code is non-zero if any mesh failed.
+    #[arg(long)]
+    pub summary_only: bool,
+
+    /// Print more details, repeat for even more
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Print the mesh each weapon record should point at, for editing by hand
+    #[arg(long)]
+    pub record_report: bool,
+
+    /// Also write the record report to this CSV file
+    #[arg(long, value_name = "PATH")]
+    pub record_report_csv: Option<PathBuf>,
+
+    /// Write a CSV report of all processed meshes
+    ///
+    /// Deprecated, use --manifest with a .csv path instead.
+    #[arg(long, value_name = "PATH", hide = true)]
+    pub csv: Option<PathBuf>,
+}
+
+impl Args {
+    /// The absolute path of the output directory.
+    ///
+    /// Symlinks are resolved once the directory exists, so the result can be
+    /// compared with other canonical paths.
+    ///
+    pub fn output_root(&self) -> PathBuf {
+        let path = match &self.base_dir {
+            Some(base_dir) => base_dir.join(&self.output_dir),
+            None => self.output_dir.clone(),
+        };
+        path.canonicalize()
+            .or_else(|_| std::path::absolute(&path))
+            .unwrap_or(path)
+    }
+
+    /// Replace the offset and scale used for the given projectile type.
+    ///
+    /// The scale of throwables is fixed, only their offset is replaced.
+    ///
+    pub fn set_transform(&mut self, projectile: Projectile, offset: f32, scale: f32) {
+        if projectile == Projectile::Thrown && self.thrown_as_arrow {
+            return self.set_transform(Projectile::Arrow, offset, scale);
+        }
+        match projectile {
+            Projectile::Arrow => {
+                self.arrow_offset = Some(offset);
+                self.arrow_scale = Some(scale);
+            }
+            Projectile::Bolt => {
+                self.bolt_offset = Some(offset);
+                self.bolt_scale = Some(scale);
+            }
+            Projectile::Thrown => self.throwable_offset = offset,
+        }
+    }
+
+    /// The transform applied to meshes of the given projectile type.
+    ///
+    /// The required arguments are absent when running a subcommand, they are
+    /// treated as zero in that case.
+    ///
+    pub fn params(&self, projectile: Projectile) -> Params {
+        if projectile == Projectile::Thrown && self.thrown_as_arrow {
+            return self.params(Projectile::Arrow);
+        }
+        let (offset, axis, scale, scale_vec, jitter, wrap) = match projectile {
+            Projectile::Arrow => (
+                self.arrow_offset.unwrap_or_default(),
+                self.arrow_axis,
+                self.arrow_scale.unwrap_or(1.0),
+                self.arrow_scale_vec,
+                self.arrow_jitter,
+                self.arrow_wrap,
+            ),
+            Projectile::Bolt => (
+                self.bolt_offset.unwrap_or_default(),
+                self.bolt_axis,
+                self.bolt_scale.unwrap_or(1.0),
+                self.bolt_scale_vec,
+                self.bolt_jitter,
+                self.bolt_wrap,
+            ),
+            Projectile::Thrown => (
+                self.throwable_offset,
+                self.throwable_axis,
+                -1.0,
+                None,
+                self.throwable_jitter,
+                self.throwable_wrap,
+            ),
+        };
+        Params {
+            projectile,
+            offset,
+            axis,
+            scale,
+            scale_vec,
+            jitter: jitter.or(self.jitter_rotation),
+            wrap,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Remove previously generated meshes from the output directory
+    Clean {
+        /// Delete without asking for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Print the transform of a processed mesh as command line arguments
+    ExtractTransform {
+        /// The NIF file to read
+        path: PathBuf,
+    },
+    /// Time parsing, transforming and serializing a directory of NIF files
+    ///
+    /// Nothing is written, the transform arguments are taken from the main
+    /// command line.
+    Bench {
+        /// Directory to search for NIF files
+        dir: PathBuf,
+
+        /// How many times to process each file
+        #[arg(long, default_value_t = 1)]
+        iterations: usize,
+
+        /// Projectile type to process the files as
+        #[arg(long, value_enum, default_value_t = Projectile::Arrow)]
+        projectile: Projectile,
+    },
+    /// Process a synthetic mesh in memory and verify the result
+    Selftest,
+    /// Write the mesh used by every projectile weapon to a JSON file
+    ///
+    /// Uses the same weapon selection as processing, but transforms nothing.
+    ExportMapping {
+        /// The JSON file to write
+        path: PathBuf,
+    },
+    /// Check the projectile meshes of the load order without writing anything
+    Audit {
+        /// Also write the results to this JSON file
+        #[arg(long, value_name = "PATH")]
+        json: Option<PathBuf>,
+    },
+    /// Compare two manifests and list the meshes that were added, removed or
+    /// changed
+    ///
+    /// Exits with status 1 if there were any differences.
+    DiffManifest {
+        /// The manifest of an earlier run
+        #[arg(value_parser = parse_manifest_path)]
+        old: PathBuf,
+        /// The manifest to compare it against
+        #[arg(value_parser = parse_manifest_path)]
+        new: PathBuf,
+    },
+}
+
+/// The projectile categories that pincushion meshes are generated for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Projectile {
+    Arrow,
+    Bolt,
+    Thrown,
+}
+
+impl Projectile {
+    /// The projectile category of a weapon type, if it is one.
+    ///
+    /// Morrowind has exactly three kinds of projectile, each with their own
+    /// parameters on the command line:
+    ///
+    /// | Weapon type      | Projectile | Arguments          |
+    /// |------------------|------------|--------------------|
+    /// | `Arrow`          | `Arrow`    | `--arrow-*`        |
+    /// | `Bolt`           | `Bolt`     | `--bolt-*`         |
+    /// | `MarksmanThrown` | `Thrown`   | `--throwable-*`    |
+    ///
+    /// There are no subtypes, thrown weapons are both the weapon and its own
+    /// ammunition. Bows and crossbows launch projectiles but never get stuck
+    /// in anything, and melee weapons aren't projectiles at all.
+    ///
+    pub fn from_weapon_type(weapon_type: WeaponType) -> Option<Self> {
+        match weapon_type {
+            WeaponType::Arrow => Some(Self::Arrow),
+            WeaponType::Bolt => Some(Self::Bolt),
+            WeaponType::MarksmanThrown => Some(Self::Thrown),
+            // Including the bows and crossbows, see above.
+            _ => None,
+        }
+    }
+}
+
+/// An output path with placeholders, see `--output-template`.
+#[derive(Clone, Debug)]
+pub struct OutputTemplate(String);
+
+impl OutputTemplate {
+    const PLACEHOLDERS: [&str; 4] = ["type", "id", "basename", "dir"];
+
+    /// Render the output path of a mesh, relative to the output directory.
+    ///
+    /// The `mesh_path` is relative to the meshes directory.
+    ///
+    pub fn render(
+        &self,
+        projectile: Projectile,
+        weapon_id: &str,
+        mesh_path: &str,
+    ) -> Result<PathBuf, String> {
+        let (dir, file_name) = mesh_path
+            .rsplit_once(['/', '\\'])
+            .unwrap_or(("", mesh_path));
+        let basename = file_name
+            .rsplit_once('.')
+            .map_or(file_name, |(stem, _)| stem);
+        let projectile = projectile.to_possible_value().unwrap();
+
+        let rendered = self
+            .0
+            .replace("{type}", projectile.get_name())
+            .replace("{id}", weapon_id)
+            .replace("{basename}", basename)
+            .replace("{dir}", dir);
+
+        // Ids may contain anything, make sure we stay within the output.
+        let illegal = ['<', '>', ':', '"', '|', '?', '*'];
+        let mut path = PathBuf::new();
+        for component in rendered.split(['/', '\\']).filter(|c| !c.is_empty()) {
+            if component == "." || component == ".." || component.contains(illegal) {
+                return Err(format!("illegal path {rendered:?}"));
+            }
+            path.push(component);
+        }
+        if path.file_name().is_none() {
+            return Err(format!("empty path {rendered:?}"));
+        }
+        Ok(path)
+    }
+}
+
+fn parse_output_template(s: &str) -> Result<OutputTemplate, String> {
+    let mut rest = s;
+    while let Some((_, after)) = rest.split_once('{') {
+        let (name, after) = after
+            .split_once('}')
+            .ok_or_else(|| format!("unclosed placeholder in {s:?}"))?;
+        if !OutputTemplate::PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder {{{name}}}, expected one of {{{}}}",
+                OutputTemplate::PLACEHOLDERS.join("}, {")
+            ));
+        }
+        rest = after;
+    }
+    Ok(OutputTemplate(s.into()))
+}
+
+/// An axis that offsets are applied along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub fn unit(self) -> Vec3 {
+        match self {
+            Self::X => Vec3::X,
+            Self::Y => Vec3::Y,
+            Self::Z => Vec3::Z,
+        }
+    }
+
+    /// The axis with the largest component in `v`.
+    pub fn dominant(v: Vec3) -> Self {
+        let v = v.abs();
+        if v.x > v.y && v.x > v.z {
+            Self::X
+        } else if v.z > v.y {
+            Self::Z
+        } else {
+            Self::Y
+        }
+    }
+}
+
+/// How collision is disabled on generated meshes.
+///
+/// The "NC" string extra data is what the engine looks for, while clearing
+/// the collision flags only helps pipelines that generate collision from
+/// node flags. Both can be applied together.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NcMethod {
+    /// Insert an "NC" string extra data on the root node
+    String,
+    /// Clear the collision flags of the root node and the transformed nodes
+    Flag,
+    /// Apply both methods
+    Both,
+}
+
+/// A numeric weapon field that offsets can be derived from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OffsetField {
+    Reach,
+    Speed,
+}
+
+impl OffsetField {
+    pub fn value(self, data: &WeaponData) -> f32 {
+        match self {
+            Self::Reach => data.reach,
+            Self::Speed => data.speed,
+        }
+    }
+}
+
+/// How to merge weapon records that conflict across the load order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ConflictPolicy {
+    /// The last plugin in the load order wins, like in the game
+    LastWins,
+    /// The first plugin to define a weapon wins
+    FirstWins,
+    /// Fail the run
+    Error,
+}
+
+/// The point that scales are applied about.
+///
+/// Scaling a node by `s` about a pivot `q` moves its translation `t` to
+/// `q + s * (t - q)`, so geometry at the pivot stays where it is. The pivot
+/// is computed from the bounding box of all targets, before any offset.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ScalePivot {
+    /// The origin of each transformed node
+    Origin,
+    /// The center of the bounding box
+    Center,
+    /// The end of the bounding box in the direction of the offset
+    Tip,
+}
+
+/// Where the "NC" string extra data is inserted.
+///
+/// Some extra data is read in order, so meshes that depend on it may need the
+/// NC entry to come after everything else.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NcPosition {
+    /// Before any existing extra data
+    Front,
+    /// After all existing extra data
+    Back,
+}
+
+/// What to do with meshes whose root can't have children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NonNodeRoot {
+    /// Wrap the root in a new node and transform it
+    Wrap,
+    /// Skip the mesh and report it, e.g. to fix it by hand
+    Skip,
+}
+
+/// When to wrap the root of a mesh in a new node.
+///
+/// Wrapping leaves the original root untouched and transforms the wrapper's
+/// only child instead, otherwise the children of the root are transformed.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WrapMode {
+    /// Only wrap roots that aren't a plain NiNode
+    Auto,
+    /// Always wrap the root
+    Always,
+    /// Never wrap, leaving meshes without a node root untransformed
+    Never,
+}
+
+/// The transform applied to a single mesh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Params {
+    pub projectile: Projectile,
+    pub offset: f32,
+    pub axis: Axis,
+    /// Negative scales flip the mesh.
+    pub scale: f32,
+    /// Additional per-axis scale, baked into the geometry.
+    pub scale_vec: Option<Vec3>,
+    /// Maximum random rotation in degrees.
+    pub jitter: Option<f32>,
+    pub wrap: WrapMode,
+}
+
+impl Params {
+    pub fn offset_vec(&self) -> Vec3 {
+        self.axis.unit() * self.offset
+    }
+}
+
+/// Parse node flags from either decimal or `0x` prefixed hex.
+fn parse_flags(s: &str) -> Result<u16, String> {
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    result.map_err(|e| format!("invalid flags {s:?}: {e}"))
+}
+
+fn parse_manifest_path(s: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    report::Format::from_path(&path)?;
+    Ok(path)
+}
+
+/// Normalize an openmw.cfg path to the absolute directory containing it.
+///
+/// Both loading and `--register` work from this one value, so they always
+/// agree on which configuration they mean.
+///
+fn parse_openmw_cfg(s: &str) -> Result<PathBuf, String> {
+    let mut path = PathBuf::from(s);
+    if path.file_name().is_some_and(|name| name == "openmw.cfg") {
+        path.pop();
+    } else if path.is_file() {
+        // OpenMW only reads files named openmw.cfg, whatever the directory.
+        return Err(format!("{s:?} is not an openmw.cfg"));
+    }
+    let path = std::path::absolute(&path).map_err(|e| format!("{s:?}: {e}"))?;
+    Ok(path.canonicalize().unwrap_or(path))
+}
+
+/// Read a list of lowercased weapon ids from a file.
+fn parse_id_list(s: &str) -> Result<HashSet<String>, String> {
+    let text = std::fs::read_to_string(s).map_err(|e| format!("{s:?}: {e}"))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+/// Parse a vector from three comma separated components.
+pub fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let components: Vec<f32> = s
+        .split(',')
+        .map(|c| c.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("invalid vector {s:?}: {e}"))?;
+
+    match components[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("expected 3 components, found {}", components.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn weapon_types_map_to_projectiles() {
+        let cases = [
+            (WeaponType::Arrow, Some(Projectile::Arrow)),
+            (WeaponType::Bolt, Some(Projectile::Bolt)),
+            (WeaponType::MarksmanThrown, Some(Projectile::Thrown)),
+            (WeaponType::MarksmanBow, None),
+            (WeaponType::MarksmanCrossbow, None),
+            (WeaponType::ShortBladeOneHand, None),
+        ];
+        for (weapon_type, projectile) in cases {
+            assert_eq!(
+                Projectile::from_weapon_type(weapon_type),
+                projectile,
+                "{weapon_type:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn each_projectile_uses_its_own_arguments() {
+        let args = fixtures::args(&[
+            "--arrow-offset=1",
+            "--arrow-scale=0.5",
+            "--bolt-offset=2",
+            "--bolt-scale=0.25",
+            "--throwable-offset=3",
+        ]);
+
+        let arrow = args.params(Projectile::Arrow);
+        assert_eq!((arrow.offset, arrow.scale), (1.0, 0.5));
+
+        let bolt = args.params(Projectile::Bolt);
+        assert_eq!((bolt.offset, bolt.scale), (2.0, 0.25));
+
+        let thrown = args.params(Projectile::Thrown);
+        assert_eq!((thrown.offset, thrown.scale), (3.0, -1.0));
+    }
+
+    #[test]
+    fn openmw_cfg_file_and_directory_normalize_alike() {
+        let dir = std::env::temp_dir();
+        let from_file = fixtures::args(&[&format!(
+            "--openmw-cfg={}",
+            dir.join("openmw.cfg").display()
+        )]);
+        let from_dir = fixtures::args(&[&format!("--openmw-cfg={}", dir.display())]);
+        assert_eq!(from_file.openmw_cfg, from_dir.openmw_cfg);
+        assert!(from_dir.openmw_cfg.unwrap().is_absolute());
+    }
+
+    #[test]
+    fn openmw_cfg_rejects_other_files() {
+        let dir = fixtures::temp_dir("openmw-cfg-name");
+        let custom = dir.join("custom.cfg");
+        std::fs::write(&custom, "").unwrap();
+
+        let arg = custom.display().to_string();
+        assert!(parse_openmw_cfg(&arg).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
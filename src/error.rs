@@ -0,0 +1,136 @@
+use std::path::PathBuf;
+
+/// The ways in which loading, processing or saving can fail.
+///
+/// The binary prints these and exits, or records them against the mesh and
+/// moves on. Variants carry the path involved, so callers can match on them
+/// instead of parsing messages.
+///
+#[derive(Debug)]
+pub enum PincushionError {
+    /// The OpenMW configuration could not be loaded.
+    ConfigLoad { reason: String },
+    /// The data directories and archives don't make a usable VFS.
+    VfsBuild { reason: String },
+    /// A content file could not be parsed.
+    PluginParse { path: PathBuf },
+    /// A mesh used by a weapon is not in the VFS.
+    MeshNotFound { path: String },
+    /// A mesh could not be read or parsed.
+    NifParse {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A mesh has other than exactly one root node.
+    InvalidRootCount { path: PathBuf, count: usize },
+    /// A mesh has no node to read a transform from, see `extract-transform`.
+    NoTransform { path: PathBuf, reason: &'static str },
+    /// A generated mesh could not be serialized or written.
+    Save {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A processed mesh is not what was expected, see `selftest`.
+    Verification { reason: String },
+}
+
+impl std::fmt::Display for PincushionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::ConfigLoad { reason } => {
+                write!(f, "Failed to load the OpenMW configuration: {reason}")
+            }
+            Self::VfsBuild { reason } => write!(f, "Failed to build the VFS: {reason}"),
+            Self::PluginParse { path } => write!(f, "Failed to parse plugin: {path:?}"),
+            Self::MeshNotFound { path } => write!(f, "File not found in VFS: {path}"),
+            Self::NifParse { path, source } => {
+                write!(f, "Failed to open NIF file at path: {path:?}: {source}")
+            }
+            Self::InvalidRootCount { path, count } => {
+                write!(f, "Invalid root node count ({count}): {path:?}")
+            }
+            Self::NoTransform { path, reason } => {
+                write!(f, "No transform to extract, {reason}: {path:?}")
+            }
+            Self::Save { path, source } => write!(f, "Failed to save {path:?}: {source}"),
+            Self::Verification { reason } => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for PincushionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::NifParse { source, .. } | Self::Save { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tes3::nif::NiStream;
+    use vfstool_lib::VFS;
+
+    use super::*;
+    use crate::bsa::Archives;
+    use crate::fixtures;
+    use crate::pincushion::{check_root_count, load_nif};
+
+    #[test]
+    fn unreadable_meshes_fail_to_parse() {
+        let dir = fixtures::temp_dir("error-nif-parse");
+        let missing = dir.join("missing.nif");
+        let garbage = dir.join("garbage.nif");
+        std::fs::write(&garbage, "NetImmerse File Format, but nothing else").unwrap();
+
+        for path in [missing, garbage] {
+            let error = load_nif(&path).err().unwrap();
+            let PincushionError::NifParse {
+                path: failed,
+                source,
+            } = &error
+            else {
+                panic!("unexpected error: {error}");
+            };
+            assert_eq!(*failed, path);
+            // The reason is kept in the message.
+            assert!(error.to_string().ends_with(&source.to_string()));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn meshes_missing_from_the_vfs_are_not_found() {
+        let dir = fixtures::temp_dir("error-mesh-not-found");
+        let vfs = VFS::from_directories(vec![dir.clone()], None);
+        let archives = Archives::open(&[], &[]);
+
+        let result = crate::resolve_mesh(&vfs, &archives, "meshes/w/missing.nif");
+
+        assert!(matches!(
+            result,
+            Err(PincushionError::MeshNotFound { path }) if path == "meshes/w/missing.nif"
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn meshes_need_a_single_root() {
+        let path = Path::new("two_roots.nif");
+        let mut stream = NiStream::default();
+        let root = fixtures::root(&mut stream, &[]);
+        assert!(check_root_count(&stream, path).is_ok());
+
+        stream.roots.push(root.cast());
+
+        assert!(matches!(
+            check_root_count(&stream, path),
+            Err(PincushionError::InvalidRootCount { count: 2, .. })
+        ));
+    }
+}
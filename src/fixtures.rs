@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+
+use clap::{Parser, crate_name};
+
+use tes3::nif::*;
+
+use crate::cli::Args;
+
+/// The arguments that are required unless a subcommand is given.
+const REQUIRED: [(&str, &str); 4] = [
+    ("--arrow-offset", "0"),
+    ("--arrow-scale", "1"),
+    ("--bolt-offset", "0"),
+    ("--bolt-scale", "1"),
+];
+
+/// Parse arguments as given on the command line.
+///
+/// Required arguments that aren't listed get a neutral value. Values must be
+/// given as `--name=value` for this to tell which are listed.
+///
+pub fn args(given: &[&str]) -> Args {
+    let mut argv = vec![crate_name!().to_string()];
+    for (name, value) in REQUIRED {
+        if !given.iter().any(|arg| arg.split('=').next() == Some(name)) {
+            argv.push(format!("{name}={value}"));
+        }
+    }
+    argv.extend(given.iter().map(|arg| arg.to_string()));
+    Args::try_parse_from(argv).unwrap()
+}
+
+/// An empty directory of a test's own, named after it.
+///
+/// Whatever a previous run of the test left there is removed first.
+///
+pub fn temp_dir(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-test-{test}", crate_name!()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// A node with the given name and children.
+pub fn node(
+    stream: &mut NiStream,
+    name: &str,
+    children: &[NiLink<NiAVObject>],
+) -> NiLink<NiAVObject> {
+    let mut node = NiNode::default();
+    node.name = name.into();
+    node.children = children.to_vec();
+    stream.insert(node).cast()
+}
+
+/// Make a new node with the given children the single root of the stream.
+pub fn root(stream: &mut NiStream, children: &[NiLink<NiAVObject>]) -> NiLink<NiAVObject> {
+    let root = node(stream, "Root", children);
+    stream.roots.push(root.cast());
+    root
+}
+
+/// A triangle shape, with vertex normals averaged from the faces around them.
+pub fn shape(
+    stream: &mut NiStream,
+    name: &str,
+    vertices: Vec<Vec3>,
+    triangles: Vec<[u16; 3]>,
+) -> NiLink<NiAVObject> {
+    let mut normals = vec![Vec3::ZERO; vertices.len()];
+    for &[a, b, c] in &triangles {
+        let [a, b, c] = [a, b, c].map(usize::from);
+        let face = (vertices[b] - vertices[a]).cross(vertices[c] - vertices[a]);
+        for i in [a, b, c] {
+            normals[i] += face;
+        }
+    }
+
+    let mut data = NiTriShapeData::default();
+    data.vertices = vertices;
+    data.normals = normals.into_iter().map(Vec3::normalize_or_zero).collect();
+    data.triangles = triangles;
+    let data = stream.insert(data);
+
+    let mut shape = NiTriShape::default();
+    shape.name = name.into();
+    shape.geometry_data = data.cast();
+    stream.insert(shape).cast()
+}
+
+/// A closed tetrahedron spanning the box from the origin to `size`.
+///
+/// Faces are wound counter-clockwise seen from the outside, and the normals
+/// point outwards, as in an authored mesh.
+///
+pub fn tetrahedron(stream: &mut NiStream, name: &str, size: Vec3) -> NiLink<NiAVObject> {
+    let vertices = vec![
+        Vec3::ZERO,
+        Vec3::X * size.x,
+        Vec3::Y * size.y,
+        Vec3::Z * size.z,
+    ];
+    let triangles = vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]];
+    shape(stream, name, vertices, triangles)
+}
@@ -0,0 +1,650 @@
+use std::collections::HashSet;
+
+use log::warn;
+
+use tes3::nif::*;
+
+/// A translation, rotation and uniform scale, as stored on NiAVObject.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Mat3,
+    pub scale: f32,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Mat3::IDENTITY,
+        scale: 1.0,
+    };
+
+    pub fn of(object: &NiAVObject) -> Self {
+        Self {
+            translation: object.translation,
+            rotation: object.rotation,
+            scale: object.scale,
+        }
+    }
+
+    /// Transform a point from local space into parent space.
+    pub fn apply(&self, point: Vec3) -> Vec3 {
+        self.rotation * (point * self.scale) + self.translation
+    }
+
+    /// The transform that applies `child` first and then `self`.
+    pub fn compose(&self, child: &Self) -> Self {
+        Self {
+            translation: self.apply(child.translation),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale * child.scale,
+        }
+    }
+
+    /// Assign this transform to an object.
+    pub fn assign(&self, object: &mut NiAVObject) {
+        object.translation = self.translation;
+        object.rotation = self.rotation;
+        object.scale = self.scale;
+    }
+
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.transpose();
+        let scale = 1.0 / self.scale;
+        Self {
+            translation: rotation * (-self.translation * scale),
+            rotation,
+            scale,
+        }
+    }
+}
+
+/// Visit the geometry data of `link` and all of its descendants.
+///
+/// The visitor receives the transform from the data's space into the space
+/// of the node that `parent` is relative to.
+///
+pub fn visit_geometry(
+    stream: &NiStream,
+    link: NiLink<NiAVObject>,
+    parent: Transform,
+    visit: &mut impl FnMut(NiLink<NiGeometryData>, Transform),
+) {
+    let Some(object) = stream.get(link) else {
+        return;
+    };
+
+    let transform = parent.compose(&Transform::of(object));
+
+    if let Some(geometry) = stream.get_as::<_, NiGeometry>(link) {
+        visit(geometry.geometry_data, transform);
+    }
+
+    if let Some(node) = stream.get_as::<_, NiNode>(link) {
+        for &child in &node.children {
+            visit_geometry(stream, child, transform, visit);
+        }
+    }
+}
+
+/// The axis aligned bounding box of the geometry below `links`, in the space
+/// that their transforms are relative to.
+pub fn bounding_box(stream: &NiStream, links: &[NiLink<NiAVObject>]) -> Option<(Vec3, Vec3)> {
+    let mut bound: Option<(Vec3, Vec3)> = None;
+
+    for &link in links {
+        visit_geometry(
+            stream,
+            link,
+            Transform::IDENTITY,
+            &mut |data_link, transform| {
+                let Some(data) = stream.get(data_link) else {
+                    return;
+                };
+                for &vertex in &data.vertices {
+                    let vertex = transform.apply(vertex);
+                    bound = Some(match bound {
+                        Some((min, max)) => (min.min(vertex), max.max(vertex)),
+                        None => (vertex, vertex),
+                    });
+                }
+            },
+        );
+    }
+
+    bound
+}
+
+/// Whether `link` or any of its descendants has skinned geometry.
+pub fn has_skin(stream: &NiStream, link: NiLink<NiAVObject>) -> bool {
+    if let Some(geometry) = stream.get_as::<_, NiGeometry>(link)
+        && stream.get(geometry.skin_instance).is_some()
+    {
+        return true;
+    }
+    stream
+        .get_as::<_, NiNode>(link)
+        .is_some_and(|node| node.children.iter().any(|&child| has_skin(stream, child)))
+}
+
+/// Whether `link` or any of its descendants has geometry.
+pub fn has_geometry(stream: &NiStream, link: NiLink<NiAVObject>) -> bool {
+    let mut found = false;
+    visit_geometry(stream, link, Transform::IDENTITY, &mut |_, _| found = true);
+    found
+}
+
+/// Whether the geometry below `link` is mirrored, i.e. turned inside out.
+///
+/// Authoring tools keep vertex normals on the side that faces are wound
+/// towards, so triangles whose winding disagrees with their normals have been
+/// mirrored since. Without normals the sign of the enclosed volume is used
+/// instead, which assumes a closed mesh. The geometry is taken with `link`'s
+/// own transform applied, so mirroring by a negative scale counts as well.
+///
+/// Returns `None` if there are no triangles to judge by.
+///
+pub fn is_mirrored(stream: &NiStream, link: NiLink<NiAVObject>) -> Option<bool> {
+    // Triangles that say mirrored count against those that say not.
+    let mut votes = 0i64;
+    let mut triangles = 0;
+
+    visit_geometry(
+        stream,
+        link,
+        Transform::IDENTITY,
+        &mut |data_link, transform| {
+            let Some(data) = stream.get_as::<_, NiTriShapeData>(data_link) else {
+                return;
+            };
+
+            let vertices: Vec<_> = data.vertices.iter().map(|&v| transform.apply(v)).collect();
+            let center = vertices.iter().sum::<Vec3>() / vertices.len().max(1) as f32;
+            let has_normals = data.normals.len() == vertices.len();
+
+            let mut volume = 0.0;
+            for triangle in &data.triangles {
+                let [a, b, c] = triangle.map(usize::from);
+                let (Some(&pa), Some(&pb), Some(&pc)) =
+                    (vertices.get(a), vertices.get(b), vertices.get(c))
+                else {
+                    continue;
+                };
+                triangles += 1;
+                if has_normals {
+                    let face = (pb - pa).cross(pc - pa);
+                    let normal: Vec3 = [a, b, c]
+                        .map(|i| transform.rotation * data.normals[i] * transform.scale)
+                        .into_iter()
+                        .sum();
+                    votes += if face.dot(normal) < 0.0 { 1 } else { -1 };
+                } else {
+                    volume += (pa - center).dot((pb - center).cross(pc - center));
+                }
+            }
+
+            if !has_normals && volume != 0.0 {
+                let count = data.triangles.len() as i64;
+                votes += if volume < 0.0 { count } else { -count };
+            }
+        },
+    );
+
+    (triangles > 0).then_some(votes > 0)
+}
+
+/// Recompute the bounding sphere of the geometry data from its vertices.
+///
+/// Extreme scales can push the result out of range, in which case the old
+/// bound is kept, a NaN or infinite radius would break culling entirely.
+///
+pub fn update_bound(data: &mut NiGeometryData) {
+    if data.vertices.is_empty() {
+        return;
+    }
+
+    let center = data.vertices.iter().sum::<Vec3>() / data.vertices.len() as f32;
+
+    let radius = data
+        .vertices
+        .iter()
+        .map(|vertex| vertex.distance(center))
+        .fold(0.0, f32::max);
+
+    if !center.is_finite() || !radius.is_finite() {
+        warn!("Bounding sphere out of range after scaling, keeping the previous bound");
+        return;
+    }
+
+    data.center = center;
+    data.radius = radius;
+}
+
+/// Bake a non-uniform scale into the geometry below `link`.
+///
+/// NiAVObject only supports uniform scales, so instead each vertex is moved
+/// into the object's local space, scaled along its axes, then moved back. The
+/// result matches what a per-axis `object.scale` would look like. Normals are
+/// scaled by the inverse and renormalized.
+///
+pub fn bake_scale(stream: &mut NiStream, link: NiLink<NiAVObject>, scale: Vec3) {
+    let Some(object) = stream.get(link) else {
+        return;
+    };
+
+    let to_local = Transform::of(object).inverse();
+
+    let mut targets = vec![];
+    visit_geometry(stream, link, Transform::IDENTITY, &mut |data, transform| {
+        targets.push((data, to_local.compose(&transform)));
+    });
+
+    // Shared geometry data must only be scaled once.
+    let mut visited = HashSet::new();
+
+    for (data_link, transform) in targets {
+        if !visited.insert(data_link.key) {
+            continue;
+        }
+
+        let Some(data) = stream.get_mut(data_link) else {
+            continue;
+        };
+
+        let inverse = transform.inverse();
+
+        for vertex in &mut data.vertices {
+            *vertex = inverse.apply(transform.apply(*vertex) * scale);
+        }
+
+        for normal in &mut data.normals {
+            let local = transform.rotation * *normal / scale;
+            *normal = (inverse.rotation * local).normalize_or_zero();
+        }
+
+        update_bound(data);
+    }
+}
+
+/// The initial state of an FNV-1a hash.
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Feed bytes into an FNV-1a hash. Fast and stable across releases, which is
+/// all that's needed here, it's not meant to resist tampering.
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A small deterministic random number generator. (SplitMix64)
+///
+/// Used instead of an external crate so that outputs stay reproducible for a
+/// given seed regardless of dependency versions.
+///
+pub struct Rng(u64);
+
+impl Rng {
+    /// Create a generator for the given seed and key, e.g. a mesh path.
+    pub fn new(seed: u64, key: &str) -> Self {
+        // Hashed, so that every key gets its own stable stream.
+        Self(seed ^ fnv1a(FNV_OFFSET, key.as_bytes()))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly distributed value in the range `[-1, 1)`.
+    pub fn next_signed(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+
+    /// A random rotation about a random axis, of at most `max_degrees`.
+    pub fn rotation(&mut self, max_degrees: f32) -> Mat3 {
+        let axis = loop {
+            let v = Vec3::new(self.next_signed(), self.next_signed(), self.next_signed());
+            // Rejection sampling for a uniform direction.
+            if (1e-4..=1.0).contains(&v.length_squared()) {
+                break v.normalize();
+            }
+        };
+        let angle = self.next_signed() * max_degrees.to_radians();
+        Mat3::from_axis_angle(axis, angle)
+    }
+}
+
+/// Find a node by name (case insensitive) among `link` and its descendants.
+pub fn find_named(
+    stream: &NiStream,
+    link: NiLink<NiAVObject>,
+    name: &str,
+) -> Option<NiLink<NiAVObject>> {
+    let object = stream.get(link)?;
+
+    if object.name.eq_ignore_ascii_case(name) {
+        return Some(link);
+    }
+
+    let node = stream.get_as::<_, NiNode>(link)?;
+
+    node.children
+        .iter()
+        .find_map(|&child| find_named(stream, child, name))
+}
+
+/// The transform from the space of `target` into the parent space of `link`.
+///
+/// Returns `None` if `target` is not `link` or one of its descendants.
+///
+pub fn transform_to(
+    stream: &NiStream,
+    link: NiLink<NiAVObject>,
+    target: NiLink<NiAVObject>,
+) -> Option<Transform> {
+    let transform = Transform::of(stream.get(link)?);
+
+    if link.key == target.key {
+        return Some(transform);
+    }
+
+    let node = stream.get_as::<_, NiNode>(link)?;
+
+    node.children
+        .iter()
+        .find_map(|&child| transform_to(stream, child, target))
+        .map(|child| transform.compose(&child))
+}
+
+/// The child of a LOD node that is shown up close, i.e. whose range has the
+/// smallest near distance.
+///
+/// Levels are usually ordered from near to far, but nothing requires it. The
+/// first child is taken if the node has no ranges.
+///
+fn nearest_level(stream: &NiStream, link: NiLink<NiAVObject>) -> NiLink<NiAVObject> {
+    let Some(lod) = stream.get_as::<_, NiLODNode>(link) else {
+        return NiLink::default();
+    };
+    let index = lod
+        .lod_levels
+        .iter()
+        .take(lod.children.len())
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.near.total_cmp(&b.near))
+        .map_or(0, |(index, _)| index);
+    lod.children.get(index).copied().unwrap_or_default()
+}
+
+/// Replace every NiLODNode below `link` with its most detailed child.
+///
+/// The kept level's transform is composed with that of the LOD node so that
+/// it stays in place. The other levels are left unreachable, to be removed by
+/// `prune_orphans`. Returns the number of LOD nodes replaced.
+///
+pub fn strip_lod(stream: &mut NiStream, link: NiLink<NiAVObject>) -> usize {
+    let Some(node) = stream.get_as::<_, NiNode>(link) else {
+        return 0;
+    };
+
+    let mut count = 0;
+
+    for (index, mut child) in node.children.clone().into_iter().enumerate() {
+        // Nested LOD nodes collapse one level at a time.
+        while let Some(NiType::NiLODNode(_)) = stream.objects.get(child.key)
+            && count < stream.objects.len()
+        {
+            count += 1;
+
+            let lod_transform = Transform::of(stream.get(child).unwrap());
+            let nearest = nearest_level(stream, child);
+
+            if let Some(object) = stream.get_mut(nearest) {
+                lod_transform.compose(&Transform::of(object)).assign(object);
+            }
+
+            stream.get_as_mut::<_, NiNode>(link).unwrap().children[index] = nearest;
+            child = nearest;
+        }
+
+        count += strip_lod(stream, child);
+    }
+
+    count
+}
+
+/// Remove all objects that are not reachable from the stream's roots.
+///
+/// Links are slot map keys rather than indices, so removing objects doesn't
+/// invalidate any of the remaining links. Indices are only assigned when the
+/// stream is saved. Returns the number of objects that were removed.
+///
+pub fn prune_orphans(stream: &mut NiStream) -> usize {
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<NiKey> = stream.roots.iter().map(|root| root.key).collect();
+
+    while let Some(key) = pending.pop() {
+        if !reachable.insert(key) {
+            continue;
+        }
+        if let Some(object) = stream.objects.get(key) {
+            object.visitor(&mut |key| pending.push(key));
+        }
+    }
+
+    let count = stream.objects.len();
+    stream.objects.retain(|key, _| reachable.contains(&key));
+    count - stream.objects.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn strip_lod_keeps_the_nearest_range() {
+        let mut stream = NiStream::default();
+        let low = fixtures::tetrahedron(&mut stream, "Low", Vec3::ONE);
+        let high = fixtures::tetrahedron(&mut stream, "High", Vec3::ONE);
+        let mut lod = NiLODNode::default();
+        lod.children = vec![low, high];
+        // Ordered from far to near.
+        lod.lod_levels = vec![
+            LODRange {
+                near: 500.0,
+                far: 4000.0,
+            },
+            LODRange {
+                near: 0.0,
+                far: 500.0,
+            },
+        ];
+        let lod = stream.insert(lod).cast();
+        let root = fixtures::root(&mut stream, &[lod]);
+
+        assert_eq!(strip_lod(&mut stream, root), 1);
+
+        let root = stream.get_as::<_, NiNode>(root).unwrap();
+        assert_eq!(root.children, [high]);
+    }
+
+    #[test]
+    fn bake_scale_scales_vertex_extents() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let node = fixtures::node(&mut stream, "Arrow", &[shape]);
+
+        bake_scale(&mut stream, node, Vec3::new(0.5, 2.0, 1.0));
+
+        let (min, max) = bounding_box(&stream, &[node]).unwrap();
+        assert!(min.abs_diff_eq(Vec3::ZERO, 1e-6));
+        assert!(max.abs_diff_eq(Vec3::new(0.5, 2.0, 1.0), 1e-6));
+
+        let data = stream.get_as::<_, NiGeometry>(shape).unwrap().geometry_data;
+        for normal in &stream.get(data).unwrap().normals {
+            assert!((normal.length() - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn bake_scale_uses_the_local_axes() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let node = fixtures::node(&mut stream, "Arrow", &[shape]);
+        stream.get_mut(node).unwrap().rotation = Mat3::from_rotation_z(std::f32::consts::FRAC_PI_2);
+
+        bake_scale(&mut stream, node, Vec3::new(2.0, 1.0, 1.0));
+
+        // The local X axis points along the parent's Y axis.
+        let (min, max) = bounding_box(&stream, &[node]).unwrap();
+        assert!(min.abs_diff_eq(Vec3::new(-1.0, 0.0, 0.0), 1e-6));
+        assert!(max.abs_diff_eq(Vec3::new(0.0, 2.0, 1.0), 1e-6));
+    }
+
+    #[test]
+    fn bake_scale_scales_shared_data_once() {
+        let mut stream = NiStream::default();
+        let first = fixtures::tetrahedron(&mut stream, "First", Vec3::ONE);
+        let mut second = NiTriShape::default();
+        second.geometry_data = stream.get_as::<_, NiGeometry>(first).unwrap().geometry_data;
+        let second = stream.insert(second).cast();
+        let node = fixtures::node(&mut stream, "Arrow", &[first, second]);
+
+        bake_scale(&mut stream, node, Vec3::new(1.0, 3.0, 1.0));
+
+        let (_, max) = bounding_box(&stream, &[node]).unwrap();
+        assert!(max.abs_diff_eq(Vec3::new(1.0, 3.0, 1.0), 1e-6));
+    }
+
+    #[test]
+    fn prune_orphans_keeps_shared_children() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let first = fixtures::node(&mut stream, "First", &[shape]);
+        let second = fixtures::node(&mut stream, "Second", &[shape]);
+        fixtures::root(&mut stream, &[first, second]);
+        let count = stream.objects.len();
+
+        assert_eq!(prune_orphans(&mut stream), 0);
+        assert_eq!(stream.objects.len(), count);
+    }
+
+    #[test]
+    fn prune_orphans_keeps_the_extra_data_chain() {
+        let mut stream = NiStream::default();
+        let root = fixtures::root(&mut stream, &[]);
+        let last = stream.insert(NiStringExtraData::default());
+        let mut first = NiStringExtraData::default();
+        first.next = last.cast();
+        let first = stream.insert(first);
+        stream
+            .get_as_mut::<_, NiObjectNET>(root)
+            .unwrap()
+            .extra_data = first.cast();
+
+        assert_eq!(prune_orphans(&mut stream), 0);
+        assert!(stream.get(first).is_some());
+        assert!(stream.get(last).is_some());
+    }
+
+    #[test]
+    fn prune_orphans_removes_unreachable_objects() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        fixtures::root(&mut stream, &[shape]);
+        let orphan = fixtures::tetrahedron(&mut stream, "Orphan", Vec3::ONE);
+        let data = stream
+            .get_as::<_, NiGeometry>(orphan)
+            .unwrap()
+            .geometry_data;
+
+        // The orphaned shape and its data.
+        assert_eq!(prune_orphans(&mut stream), 2);
+        assert!(stream.get(orphan).is_none());
+        assert!(stream.get(data).is_none());
+        assert!(stream.get(shape).is_some());
+    }
+
+    #[test]
+    fn update_bound_keeps_the_bound_when_out_of_range() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let huge = fixtures::tetrahedron(&mut stream, "Huge", Vec3::splat(1e33));
+        let node = fixtures::node(&mut stream, "Arrow", &[shape, huge]);
+        let data = |stream: &NiStream, link: NiLink<NiAVObject>| {
+            let data = stream.get_as::<_, NiGeometry>(link).unwrap().geometry_data;
+            stream.get(data).unwrap().clone()
+        };
+        let previous = data(&stream, huge);
+
+        bake_scale(&mut stream, node, Vec3::splat(1e6));
+
+        // Large but in range, the bound follows the geometry.
+        let scaled = data(&stream, shape);
+        assert!(scaled.radius.is_finite() && scaled.radius > 1e5);
+
+        // Pushed past the range of f32, the bound is left as it was.
+        let overflowed = data(&stream, huge);
+        assert_eq!(overflowed.center, previous.center);
+        assert_eq!(overflowed.radius, previous.radius);
+    }
+
+    #[test]
+    fn authored_geometry_is_not_mirrored() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let node = fixtures::node(&mut stream, "Dart", &[shape]);
+
+        assert_eq!(is_mirrored(&stream, node), Some(false));
+    }
+
+    #[test]
+    fn mirrored_vertex_data_is_detected() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let node = fixtures::node(&mut stream, "Dart", &[shape]);
+
+        bake_scale(&mut stream, node, Vec3::NEG_ONE);
+        assert_eq!(is_mirrored(&stream, node), Some(true));
+
+        // Mirroring it back by the transform restores it.
+        stream.get_mut(node).unwrap().scale = -1.0;
+        assert_eq!(is_mirrored(&stream, node), Some(false));
+    }
+
+    #[test]
+    fn mirrored_transforms_are_detected() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let node = fixtures::node(&mut stream, "Dart", &[shape]);
+        stream.get_mut(node).unwrap().scale = -1.0;
+
+        assert_eq!(is_mirrored(&stream, node), Some(true));
+    }
+
+    #[test]
+    fn mirroring_without_normals_uses_the_volume() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let node = fixtures::node(&mut stream, "Dart", &[shape]);
+        let data = stream.get_as::<_, NiGeometry>(shape).unwrap().geometry_data;
+        stream.get_mut(data).unwrap().normals.clear();
+
+        assert_eq!(is_mirrored(&stream, node), Some(false));
+        stream.get_mut(node).unwrap().scale = -1.0;
+        assert_eq!(is_mirrored(&stream, node), Some(true));
+    }
+
+    #[test]
+    fn nothing_to_judge_without_triangles() {
+        let mut stream = NiStream::default();
+        let node = fixtures::node(&mut stream, "Dart", &[]);
+
+        assert_eq!(is_mirrored(&stream, node), None);
+    }
+}
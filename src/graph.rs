@@ -0,0 +1,110 @@
+use std::fmt::Write;
+
+use tes3::nif::*;
+
+/// Collects written text up to the first '(', then refuses any more.
+struct UntilParen(String);
+
+impl Write for UntilParen {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        match s.split_once('(') {
+            Some((head, _)) => {
+                self.0 += head;
+                Err(std::fmt::Error)
+            }
+            None => {
+                self.0 += s;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The name of a NIF type, e.g. "NiTriShape".
+///
+/// Taken from the `Debug` output of the object since the types carry no name
+/// of their own. Formatting stops at the variant's name, so the contents of
+/// large objects like geometry data are never formatted.
+///
+fn type_name(object: &NiType) -> String {
+    let mut name = UntilParen(String::new());
+    let _ = write!(name, "{object:?}");
+    name.0
+}
+
+/// Render the scene graph of a stream as indented text, one object per line.
+///
+/// Lists the name, type and transform of every node, along with the extra
+/// data attached to it, for comparing meshes before and after processing.
+///
+pub fn dump(stream: &NiStream) -> String {
+    let mut out = String::new();
+    for &root in &stream.roots {
+        write_object(stream, root.cast(), 0, &mut out);
+    }
+    out
+}
+
+fn write_object(stream: &NiStream, link: NiLink<NiAVObject>, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+
+    let Some(object) = stream.objects.get(link.key) else {
+        let _ = writeln!(out, "{indent}<missing>");
+        return;
+    };
+
+    // Guard against cyclic graphs in malformed files.
+    if depth > stream.objects.len() {
+        let _ = writeln!(out, "{indent}<cycle>");
+        return;
+    }
+
+    let _ = write!(out, "{indent}{}", type_name(object));
+
+    if let Some(object) = stream.get(link) {
+        let (t, r) = (object.translation, object.rotation);
+        let _ = write!(
+            out,
+            " {:?} translation=[{}, {}, {}] rotation=[{:?}, {:?}, {:?}] scale={} flags={:#06x}",
+            object.name, t.x, t.y, t.z, r.x_axis, r.y_axis, r.z_axis, object.scale, object.flags,
+        );
+    }
+    let _ = writeln!(out);
+
+    if let Some(object) = stream.get_as::<_, NiObjectNET>(link) {
+        let mut next = object.extra_data;
+        let mut count = 0;
+        while let Some(extra_data) = stream.get(next)
+            && count < stream.objects.len()
+        {
+            let description = match stream.get_as::<_, NiStringExtraData>(next) {
+                Some(string) => format!("NiStringExtraData {:?}", string.value),
+                None => type_name(&stream.objects[next.key]),
+            };
+            let _ = writeln!(out, "{indent}  + {description}");
+            next = extra_data.next;
+            count += 1;
+        }
+    }
+
+    if let Some(node) = stream.get_as::<_, NiNode>(link) {
+        for &child in &node.children {
+            write_object(stream, child, depth + 1, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_name_is_the_variant_name() {
+        let mut stream = NiStream::default();
+        let mut data = NiTriShapeData::default();
+        data.vertices = vec![Vec3::ZERO; 1000];
+        let data = stream.insert(data);
+
+        assert_eq!(type_name(&stream.objects[data.key]), "NiTriShapeData");
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+/// The name of the ignore files read from each data directory.
+pub const IGNORE_FILE: &str = ".pincushionignore";
+
+/// A single pattern and where it was defined.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub pattern: String,
+    pub source: PathBuf,
+    pub line: usize,
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.source.display(),
+            self.line,
+            self.pattern
+        )
+    }
+}
+
+/// Mesh path globs to skip, merged from the ignore files of all data
+/// directories.
+///
+/// Patterns are matched case insensitively against mesh paths relative to
+/// the meshes directory, e.g. "w/w_arrow01.nif". A `*` matches within a
+/// single directory, `**` matches across directories and `?` matches one
+/// character. Patterns without a `/` match the file name in any directory,
+/// like in gitignore. Blank lines and lines starting with `#` are ignored.
+///
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreRules {
+    pub rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    pub fn from_directories(dirs: &[PathBuf]) -> Self {
+        let mut rules = Self::default();
+        for dir in dirs {
+            let path = dir.join(IGNORE_FILE);
+            if !path.is_file() {
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(text) => rules.parse(&path, &text),
+                Err(error) => warn!("Failed to read {path:?}: {error}"),
+            }
+        }
+        rules
+    }
+
+    fn parse(&mut self, source: &Path, text: &str) {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.rules.push(Rule {
+                pattern: normalize(line),
+                source: source.to_path_buf(),
+                line: index + 1,
+            });
+        }
+    }
+
+    /// The first rule matching a mesh path, if any.
+    pub fn matches(&self, mesh_path: &str) -> Option<&Rule> {
+        let path = normalize(mesh_path);
+        let path = path.trim_start_matches('/');
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+
+        self.rules.iter().find(|rule| {
+            let pattern = rule.pattern.trim_start_matches('/');
+            if rule.pattern.contains('/') {
+                glob_match(pattern.as_bytes(), path.as_bytes())
+            } else {
+                glob_match(pattern.as_bytes(), file_name.as_bytes())
+            }
+        })
+    }
+}
+
+/// Lowercase and use forward slashes, mesh paths in records use either.
+fn normalize(path: &str) -> String {
+    path.replace('\\', "/").to_lowercase()
+}
+
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // "**/" may also match no directories at all.
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=path.len()).any(|i| glob_match(rest, &path[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let end = path.iter().position(|&c| c == b'/').unwrap_or(path.len());
+            (0..=end).any(|i| glob_match(rest, &path[i..]))
+        }
+        [b'?', rest @ ..] => match path {
+            [c, tail @ ..] if *c != b'/' => glob_match(rest, tail),
+            _ => false,
+        },
+        [c, rest @ ..] => match path {
+            [d, tail @ ..] if c == d => glob_match(rest, tail),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rules parsed from the given ignore file contents.
+    fn rules(text: &str) -> IgnoreRules {
+        let mut rules = IgnoreRules::default();
+        rules.parse(Path::new(IGNORE_FILE), text);
+        rules
+    }
+
+    #[test]
+    fn double_star_matches_any_number_of_directories() {
+        let start = rules("**/a.nif");
+        assert!(start.matches("a.nif").is_some());
+        assert!(start.matches("w/x/a.nif").is_some());
+        assert!(start.matches("w/b.nif").is_none());
+
+        let middle = rules("w/**/a.nif");
+        assert!(middle.matches("w/a.nif").is_some());
+        assert!(middle.matches("w/x/y/a.nif").is_some());
+        assert!(middle.matches("v/x/a.nif").is_none());
+
+        let end = rules("w/**");
+        assert!(end.matches("w/a.nif").is_some());
+        assert!(end.matches("w/x/a.nif").is_some());
+        assert!(end.matches("v/a.nif").is_none());
+    }
+
+    #[test]
+    fn single_star_stays_within_a_directory() {
+        let rules = rules("w/*.nif");
+        assert!(rules.matches("w/a.nif").is_some());
+        assert!(rules.matches("w/x/a.nif").is_none());
+    }
+
+    #[test]
+    fn matching_ignores_case_and_separators() {
+        let rules = rules(r"W\Arrow?1.NIF");
+        assert!(rules.matches(r"w\ARROW01.nif").is_some());
+        assert!(rules.matches("w/arrow/1.nif").is_none());
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let rules = rules("# arrows\n\n   \n  # indented\nw/a.nif\n");
+        assert_eq!(rules.rules.len(), 1);
+        assert_eq!(rules.rules[0].pattern, "w/a.nif");
+        assert_eq!(rules.rules[0].line, 5);
+    }
+}
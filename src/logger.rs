@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Number of warnings logged so far, regardless of whether they were shown.
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// The log file given by `--log-file`, if any.
+static LOG_FILE: Mutex<Option<LineWriter<File>>> = Mutex::new(None);
+
+/// A minimal logger, writing warnings and errors to stderr and the rest to stdout.
+///
+/// If a log file is open every message is written to it, whatever the console
+/// verbosity is. With `stderr` set everything goes to stderr, leaving stdout
+/// free for output.
+///
+struct Logger {
+    console: LevelFilter,
+    stderr: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() == Level::Warn {
+            WARNINGS.fetch_add(1, Ordering::Relaxed);
+        }
+        write_file(format_args!("[{}] {}", record.level(), record.args()));
+        if record.level() > self.console {
+            return;
+        }
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("{}", record.args()),
+            _ if self.stderr => eprintln!("{}", record.args()),
+            _ => println!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// The number of warnings logged so far.
+pub fn warning_count() -> usize {
+    WARNINGS.load(Ordering::Relaxed)
+}
+
+/// Write a line to the log file only, e.g. details that would clutter the
+/// console but help reproduce a run.
+pub fn write_file(line: std::fmt::Arguments) {
+    if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+        let _ = writeln!(file, "{line}");
+    }
+}
+
+fn install(console: LevelFilter, log_file: Option<&Path>, stderr: bool) -> std::io::Result<()> {
+    let max_level = match log_file {
+        Some(path) => {
+            *LOG_FILE.lock().unwrap() = Some(LineWriter::new(File::create(path)?));
+            LevelFilter::Trace
+        }
+        None => console,
+    };
+    log::set_logger(Box::leak(Box::new(Logger { console, stderr }))).unwrap();
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Install the logger, each level of verbosity enables more messages.
+pub fn init(verbosity: u8, log_file: Option<&Path>, stderr: bool) -> std::io::Result<()> {
+    install(
+        match verbosity {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        },
+        log_file,
+        stderr,
+    )
+}
+
+/// Install the logger, only showing warnings and errors.
+pub fn init_quiet(log_file: Option<&Path>, stderr: bool) -> std::io::Result<()> {
+    install(LevelFilter::Warn, log_file, stderr)
+}
@@ -1,198 +1,2515 @@
-use std::collections::HashMap;
-use std::path::Path;
+mod bench;
+mod bsa;
+mod cli;
+mod error;
+#[cfg(test)]
+mod fixtures;
+mod geometry;
+mod graph;
+mod ignore;
+mod logger;
+mod overrides;
+mod pincushion;
+mod profile;
+mod report;
+mod selftest;
 
-use clap::Parser;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 
+use clap::{Parser, ValueEnum, crate_name, crate_version};
+use log::{debug, error, info, warn};
+
+use notify::{RecursiveMode, Watcher};
 use openmw_config::OpenMWConfiguration;
 use vfstool_lib::VFS;
 
 use tes3::esp::*;
 use tes3::nif::*;
 
-/// For arrows we offset translation and reduce scale
-fn process_arrow(object: &mut NiAVObject, args: &Args) {
-    object.translation.y += args.arrow_offset;
-    object.scale *= args.arrow_scale;
-}
+use bsa::Archives;
+use cli::{Args, Axis, Command, ConflictPolicy, NonNodeRoot, Params, Projectile};
+use error::PincushionError;
+use ignore::IgnoreRules;
+use pincushion::{
+    Marker, apply_pincushion, check_root_count, content_hash, has_marker_tag, has_nif_header,
+    has_no_collision_tag, insert_marker_hash, load_nif, needs_root_wrapper, read_marker_tag,
+    read_nif_version, template_stream,
+};
+use report::{
+    AuditReport, Bounds, BoundsEntry, GalleryEntry, ManifestDiff, MappingEntry, MeshRecord,
+    ProcessReport, RecordEntry, SharedMesh, Status,
+};
 
-/// For bolts we just shift them forward slightly
-fn process_bolt(object: &mut NiAVObject, args: &Args) {
-    object.translation.y += args.bolt_offset;
-    object.scale *= args.bolt_scale;
+/// Replace the extension of a lowercased mesh path with the original one.
+///
+/// References on case sensitive file systems must match the authored casing.
+///
+fn with_original_extension(mesh_path: &str, original: &str) -> String {
+    match (mesh_path.rsplit_once('.'), original.rsplit_once('.')) {
+        (Some((stem, _)), Some((_, extension))) => format!("{stem}.{extension}"),
+        _ => mesh_path.to_string(),
+    }
 }
 
-/// For throwables we just flip them. (-1 scale)
-fn process_throwable(object: &mut NiAVObject) {
-    object.scale *= -1.0;
+/// Insert a suffix between the file stem and extension of a path.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
 }
 
-/// Insert a new parent node above the previous root node.
+/// Strip a leading "meshes" directory from a mesh path, if present.
 ///
-/// The engine ignores transformations on root nodes, so we must
-/// do this before we can transform the original file root node.
+/// Mesh paths are relative to the meshes directory, but some records include
+/// it anyway, which would otherwise be looked up as "meshes/meshes/...".
 ///
-fn insert_root_parent(stream: &mut NiStream) -> &mut NiNode {
-    let mut node = NiNode::default();
-
-    // Make all previous roots children of the new node.
-    for root in &stream.roots {
-        node.children.push(root.cast());
+fn strip_meshes_prefix(mesh_path: &str) -> &str {
+    let path = mesh_path.trim_start_matches(['/', '\\']);
+    match path.split_once(['/', '\\']) {
+        Some((first, rest)) if first.eq_ignore_ascii_case("meshes") => rest,
+        _ => path,
     }
+}
 
-    // Insert the new node and assign it as the scene root.
-    let link = stream.insert(node);
-    stream.roots.clear();
-    stream.roots.push(link.cast());
+/// Set once Ctrl-C is pressed, the current mesh is finished before stopping.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
-    stream.get_mut(link).unwrap()
+/// Exit code after an interrupted run, as shells use for SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
 }
 
-fn insert_no_collision_tag(stream: &mut NiStream) {
-    let mut extra_data = NiStringExtraData::default();
-    extra_data.value = "NC".into();
+/// Write a file by writing a temporary file next to it and renaming it into
+/// place, so the target is always either the old or the new file.
+///
+/// Renames can only fail across devices if the output directory is a mount
+/// point of its own, in which case we fall back to copying.
+///
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".tmp");
+
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp_path)?;
+        std::io::Write::write_all(&mut file, bytes)?;
+        file.sync_all()?;
+        drop(file);
+
+        match std::fs::rename(&temp_path, path) {
+            Err(error) if error.kind() == std::io::ErrorKind::CrossesDevices => {
+                std::fs::copy(&temp_path, path)?;
+                std::fs::remove_file(&temp_path)
+            }
+            result => result,
+        }
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+
+    result
+}
 
-    let extra_data_link = stream.insert(extra_data);
+/// Write a generated file, creating its parent directories as needed.
+fn write_output(path: &Path, bytes: &[u8]) -> Result<(), PincushionError> {
+    let error = |source| PincushionError::Save {
+        path: path.to_path_buf(),
+        source,
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(error)?;
+    }
+    write_atomic(path, bytes).map_err(error)
+}
 
-    let root = stream
-        .get_as_mut::<_, NiObjectNET>(stream.roots[0])
-        .unwrap();
+/// Serialize and write a generated mesh.
+fn save_nif(stream: &NiStream, path: &Path) -> Result<(), PincushionError> {
+    let bytes = stream
+        .save_bytes()
+        .map_err(|source| PincushionError::Save {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    write_output(path, &bytes)
+}
 
-    // Store the previous extra data
-    let next_extra_data = root.extra_data;
+/// Print an error and exit with a failure status.
+fn fail(error: PincushionError) -> ! {
+    error!("{error}");
+    std::process::exit(1);
+}
 
-    // Assign the new extra data
-    root.extra_data = extra_data_link.cast();
+/// How long to wait for more file events before reprocessing.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
 
-    // Insert the previous after the new one
-    let extra_data = stream.get_mut(extra_data_link).unwrap();
-    extra_data.next = next_extra_data;
+/// How many weapon records a single plugin defined.
+#[derive(Clone, Copy, Debug, Default)]
+struct WeaponCounts {
+    weapons: usize,
+    projectiles: usize,
+    /// Malformed records are skipped, but included in `weapons`.
+    malformed: usize,
+    /// Records that redefine the mesh or type of an earlier one.
+    conflicts: usize,
+    /// The plugin couldn't be parsed, none of its records were loaded.
+    failed: bool,
 }
 
-fn process_plugin(args: &Args, vfs: &VFS, plugin_path: &Path) {
+/// Load the weapon records of a plugin into `weapons`, see `merge_weapons`.
+fn load_weapons(
+    plugin_path: &Path,
+    weapons: &mut HashMap<String, Weapon>,
+    origins: &mut HashMap<String, String>,
+    policy: ConflictPolicy,
+) -> WeaponCounts {
     let filter = |tag| tag == *Weapon::TAG;
 
-    let Ok(plugin) = Plugin::from_path_filtered(&plugin_path, filter) else {
-        eprintln!("Failed to parse plugin: {plugin_path:?}");
-        return;
+    let plugin = match Plugin::from_path_filtered(&plugin_path, filter) {
+        Ok(plugin) => plugin,
+        Err(_) => {
+            let path = plugin_path.to_path_buf();
+            error!("{}", PincushionError::PluginParse { path });
+            return WeaponCounts {
+                failed: true,
+                ..Default::default()
+            };
+        }
     };
 
-    // Gather all projectile meshes in the plugin.
+    let plugin_name = plugin_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    merge_weapons(
+        &plugin_name,
+        plugin.objects_of_type::<Weapon>(),
+        weapons,
+        origins,
+        policy,
+    )
+}
+
+/// Merge the weapon records of a plugin into `weapons`.
+///
+/// Records replace any previously loaded record with the same id, so merging
+/// plugins in load order leaves only the winning definitions. Records that
+/// change the mesh or type of an earlier one are resolved by `policy`, and
+/// warned about with the plugins involved. `origins` tracks the plugin each
+/// loaded record came from for this purpose.
+///
+fn merge_weapons<'a>(
+    plugin_name: &str,
+    plugin_weapons: impl IntoIterator<Item = &'a Weapon>,
+    weapons: &mut HashMap<String, Weapon>,
+    origins: &mut HashMap<String, String>,
+    policy: ConflictPolicy,
+) -> WeaponCounts {
+    let mut counts = WeaponCounts::default();
+
+    for weapon in plugin_weapons {
+        counts.weapons += 1;
+        // A record without its data block would be classified by the default
+        // weapon type, and one without a mesh has nothing to process.
+        if weapon.mesh.is_empty() || weapon.data == WeaponData::default() {
+            warn!(
+                "Skipping malformed weapon record {:?} in {plugin_name}",
+                weapon.id
+            );
+            counts.malformed += 1;
+            continue;
+        }
+        if Projectile::from_weapon_type(weapon.data.weapon_type).is_some() {
+            counts.projectiles += 1;
+        }
+
+        let id = weapon.id.to_lowercase();
+
+        // Only differences that change what we generate count as conflicts.
+        // Those are most likely distinct weapons that happen to share an id,
+        // rather than an intended override.
+        if let Some(existing) = weapons.get(&id)
+            && (!existing.mesh.eq_ignore_ascii_case(&weapon.mesh)
+                || existing.data.weapon_type != weapon.data.weapon_type)
+        {
+            counts.conflicts += 1;
+            let earlier = origins.get(&id).map_or("an earlier plugin", String::as_str);
+            match policy {
+                ConflictPolicy::LastWins => warn!(
+                    "Weapon {:?} is defined differently by {earlier} and {plugin_name}, using {plugin_name}",
+                    weapon.id
+                ),
+                ConflictPolicy::FirstWins => {
+                    warn!(
+                        "Weapon {:?} is defined differently by {earlier} and {plugin_name}, using {earlier}",
+                        weapon.id
+                    );
+                    continue;
+                }
+                ConflictPolicy::Error => error!(
+                    "Weapon {:?} is defined differently by {earlier} and {plugin_name}",
+                    weapon.id
+                ),
+            }
+        }
+
+        origins.insert(id.clone(), plugin_name.to_string());
+        weapons.insert(id, weapon.clone());
+    }
+
+    counts
+}
+
+/// Whether a weapon is a placeholder for a spell projectile.
+///
+/// Neither the weapon flags (silver, ignore normal weapon resistance) nor the
+/// record flags (deleted, persistent, blocked) mark these, so the "VFX_" id
+/// prefix used by the game is all there is to go by. It is matched without
+/// regard to case, as the engine looks up ids case-insensitively.
+///
+fn is_spell_vfx(weapon: &Weapon) -> bool {
+    weapon
+        .id
+        .get(..4)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("VFX_"))
+}
 
-    let projectiles: HashMap<_, _> = plugin
-        .objects_of_type::<Weapon>()
-        .filter_map(|weapon| {
-            // Skip spell projectile VFX types.
-            if weapon.id.starts_with("VFX_") {
+/// The projectile type of a weapon, if it should be processed.
+///
+/// The `id` is expected to be lowercased.
+///
+fn projectile_type(args: &Args, id: &str, weapon: &Weapon) -> Option<Projectile> {
+    match &args.id_list {
+        // Only listed weapons, as long as we know how to process them.
+        Some(ids) => {
+            if !ids.contains(id) {
                 return None;
             }
-            // Skip non-projectile weapon types.
-            match weapon.data.weapon_type {
-                WeaponType::MarksmanThrown | WeaponType::Arrow | WeaponType::Bolt => {}
-                _ => return None,
+            if Projectile::from_weapon_type(weapon.data.weapon_type).is_none() {
+                warn!("Listed weapon is not a projectile: {:?}", weapon.id);
             }
-            // Mesh path as key for de-duplication.
-            Some((weapon.mesh.to_lowercase(), weapon))
-        })
-        .collect();
+        }
+        // Skip spell projectile VFX types.
+        None => {
+            if is_spell_vfx(weapon) {
+                return None;
+            }
+        }
+    }
+    // Skip non-projectile weapon types.
+    Projectile::from_weapon_type(weapon.data.weapon_type)
+}
+
+/// The lowercased mesh path of a weapon, relative to the meshes directory.
+///
+/// Records may use either separator, so forward slashes are used throughout
+/// and the path is only made native when it's joined onto the output path.
+///
+fn mesh_key(weapon: &Weapon) -> String {
+    strip_meshes_prefix(&weapon.mesh.to_lowercase()).replace('\\', "/")
+}
+
+/// A hash of the weapon record fields that a generated mesh depends on.
+fn record_hash(weapon: &Weapon) -> String {
+    let fields = format!(
+        "{} {} {:?}",
+        weapon.id.to_lowercase(),
+        weapon.mesh.to_lowercase(),
+        weapon.data
+    );
+    format!(
+        "{:016x}",
+        geometry::fnv1a(geometry::FNV_OFFSET, fields.as_bytes())
+    )
+}
+
+/// The projectile meshes to generate, see `gather_projectiles`.
+struct Gathered<'a> {
+    /// Mesh path, weapon, type, parameters and whether the mesh is split
+    /// between several weapons.
+    projectiles: Vec<(String, &'a Weapon, Projectile, Params, bool)>,
+    /// Meshes that are only used by weapons skipped by --overrides.
+    skipped: BTreeMap<String, (&'a Weapon, Params)>,
+}
+
+/// Gather the projectile meshes of the given weapons, with the parameters
+/// each is generated with.
+fn gather_projectiles<'a>(args: &Args, weapons: &'a HashMap<String, Weapon>) -> Gathered<'a> {
+    if let Some(ids) = &args.id_list {
+        for id in ids.iter().filter(|id| !weapons.contains_key(*id)) {
+            warn!("Listed weapon id not found: {id:?}");
+        }
+    }
+
+    // Sorted by mesh path, so that log lines and records come out in a stable
+    // order between runs. Weapons are visited in id order, so the same one is
+    // chosen whenever several share a mesh.
+
+    let mut ids: Vec<_> = weapons.keys().collect();
+    ids.sort();
+
+    // Mesh path as key for de-duplication. Weapons that share a mesh but want
+    // a different transform, e.g. a different type or reach, are kept apart.
+    let mut groups: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    let mut skipped = BTreeMap::new();
+
+    for id in ids {
+        let weapon = &weapons[id];
+        let Some(projectile) = projectile_type(args, id, weapon) else {
+            continue;
+        };
+
+        let mut params = args.params(projectile);
+        if let Some(field) = args.offset_from_field {
+            params.offset = field.value(&weapon.data) * args.offset_field_scale;
+        }
+
+        let mesh_path = mesh_key(weapon);
+        if let Some(values) = args
+            .overrides
+            .as_ref()
+            .and_then(|overrides| overrides.get(id, &mesh_path))
+        {
+            if values.skip {
+                debug!("Skipping weapon {:?}, as listed in --overrides", weapon.id);
+                skipped.entry(mesh_path).or_insert((weapon, params));
+                continue;
+            }
+            values.apply(&mut params);
+        }
+
+        let group = groups.entry(mesh_path).or_default();
+        if group.iter().all(|&(_, _, other)| other != params) {
+            group.push((weapon, projectile, params));
+        }
+    }
+
+    // Meshes still used by other weapons are processed for those.
+    skipped.retain(|mesh_path, _| !groups.contains_key(mesh_path));
+
+    let mut projectiles = vec![];
+
+    for (mesh_path, group) in groups {
+        let split = group.len() > 1;
+        if split {
+            let ids: Vec<_> = group
+                .iter()
+                .map(|(weapon, ..)| weapon.id.as_str())
+                .collect();
+            warn!("Splitting shared mesh {mesh_path:?} between weapons {ids:?}");
+        }
+        for (weapon, projectile, params) in group {
+            projectiles.push((mesh_path.clone(), weapon, projectile, params, split));
+        }
+    }
+
+    Gathered {
+        projectiles,
+        skipped,
+    }
+}
+
+/// Process the projectile meshes of the given weapons, optionally limited to
+/// the given set of changed meshes. Meshes matching `ignore` are skipped.
+///
+/// The `changed` paths are expected to be canonicalized.
+///
+fn process_weapons(
+    args: &Args,
+    vfs: &VFS,
+    archives: &Archives,
+    weapons: &HashMap<String, Weapon>,
+    ignore: &IgnoreRules,
+    changed: Option<&HashSet<PathBuf>>,
+    records: &mut Vec<MeshRecord>,
+) {
+    let Gathered {
+        projectiles,
+        skipped,
+    } = gather_projectiles(args, weapons);
+
+    for (mesh_path, (weapon, params)) in skipped {
+        records.push(MeshRecord {
+            weapon_id: weapon.id.clone(),
+            weapon_type: format!("{:?}", weapon.data.weapon_type),
+            source_path: format!("meshes/{mesh_path}"),
+            output_path: String::new(),
+            offset: params.offset,
+            scale: params.scale,
+            status: Status::Skipped,
+            wrap_root: false,
+            had_no_collision: false,
+            profile: String::new(),
+            reason: "override".into(),
+            record_hash: record_hash(weapon),
+            bounds: None,
+            mesh: weapon.mesh.clone(),
+        });
+    }
 
     // Process each projectile mesh.
 
-    let output_path = Path::new("openmw_pincushion_generator");
+    let output_root = args.output_root();
+    let output_path = output_root.as_path();
+    let mut output_paths = HashSet::new();
+    let mut ignored = BTreeMap::new();
+
+    // The records of the previous run, by output path.
+    let previous: HashMap<_, _> = match &args.manifest {
+        Some(path) if args.only_changed_records && path.exists() => report::read_manifest(path)
+            .unwrap_or_else(|error| {
+                warn!("Ignoring unreadable manifest {path:?}: {error}");
+                vec![]
+            })
+            .into_iter()
+            .map(|record| (record.output_path.to_lowercase(), record))
+            .collect(),
+        _ => HashMap::new(),
+    };
+
+    for (mesh_path, weapon, projectile, params, split) in projectiles {
+        if interrupted() {
+            warn!("Interrupted, stopping after the current mesh");
+            break;
+        }
 
-    for (mesh_path, weapon) in projectiles {
         let with_prefix = format!("meshes/{}", mesh_path);
 
-        let Some(vfs_path) = vfs.get_file(&with_prefix) else {
-            eprintln!("File not found in VFS: {mesh_path}");
+        let mut record = MeshRecord {
+            weapon_id: weapon.id.clone(),
+            weapon_type: format!("{:?}", weapon.data.weapon_type),
+            source_path: with_prefix.clone(),
+            output_path: String::new(),
+            offset: params.offset,
+            scale: params.scale,
+            status: Status::Saved,
+            wrap_root: false,
+            had_no_collision: false,
+            profile: String::new(),
+            reason: String::new(),
+            record_hash: record_hash(weapon),
+            bounds: None,
+            mesh: weapon.mesh.clone(),
+        };
+
+        // Checked before resolving, so that archived meshes aren't extracted
+        // again on every event. Only loose files are watched for changes.
+        if let Some(changed) = changed
+            && !matches!(
+                locate_mesh(vfs, archives, &with_prefix),
+                Some(MeshLocation::Loose(path))
+                    if path.canonicalize().is_ok_and(|path| changed.contains(&path))
+            )
+        {
+            continue;
+        }
+
+        if let Some(rule) = ignore.matches(&mesh_path) {
+            debug!("Ignoring {mesh_path:?}, matched by {rule}");
+            *ignored.entry(rule.to_string()).or_insert(0) += 1;
+            records.push(MeshRecord {
+                status: Status::Skipped,
+                reason: "ignored".into(),
+                ..record
+            });
             continue;
+        }
+
+        let resolved = match resolve_mesh(vfs, archives, &with_prefix) {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                error!("{error}");
+                records.push(MeshRecord {
+                    status: Status::NotFound,
+                    ..record
+                });
+                continue;
+            }
         };
+        let abs_path = resolved.path.as_path();
 
-        let abs_path = vfs_path.path();
+        record.source_path = resolved.origin.display().to_string();
 
-        let Ok(mut stream) = NiStream::from_path(abs_path) else {
-            eprintln!("Failed to open NIF file at path: {abs_path:?}");
+        if !abs_path.is_file() {
+            error!("Mesh path resolved to something other than a file: {abs_path:?}");
+            records.push(MeshRecord {
+                status: Status::NotAFile,
+                ..record
+            });
+            continue;
+        }
+
+        if let Some(min_version) = args.min_nif_version
+            && read_nif_version(abs_path).is_some_and(|version| version < min_version)
+        {
+            warn!("Skipping mesh below the minimum NIF version: {abs_path:?}");
+            records.push(MeshRecord {
+                status: Status::Skipped,
+                reason: "nif-version".into(),
+                ..record
+            });
             continue;
+        }
+
+        let stream = match load_nif(abs_path) {
+            Ok(stream) => stream,
+            Err(error) => {
+                let status = if has_nif_header(abs_path) {
+                    error!("{error}");
+                    Status::ParseFailed
+                } else {
+                    error!("Not a NIF file, check for a mod shadowing this mesh: {abs_path:?}");
+                    Status::NotNif
+                };
+                records.push(MeshRecord { status, ..record });
+                continue;
+            }
         };
 
-        if stream.roots.len() != 1 {
-            eprintln!("Invalid root node count: {abs_path:?}",);
+        if stream.roots.is_empty() {
+            warn!("Mesh has no root nodes, skipping: {abs_path:?}");
+            records.push(MeshRecord {
+                status: Status::Skipped,
+                reason: "no-roots".into(),
+                ..record
+            });
+            continue;
+        }
+
+        if let Err(error) = check_root_count(&stream, abs_path) {
+            error!("{error}");
+            records.push(MeshRecord {
+                status: Status::InvalidRootCount,
+                ..record
+            });
             continue;
         }
 
-        let root = match stream.objects.get(stream.roots[0].key) {
-            Some(NiType::NiNode(node)) => node,
-            _ => insert_root_parent(&mut stream),
+        if has_marker_tag(&stream) && !args.reprocess {
+            info!("Skipping previously generated mesh: {abs_path:?}");
+            records.push(MeshRecord {
+                status: Status::Skipped,
+                ..record
+            });
+            continue;
+        }
+
+        if args.non_node_root == NonNodeRoot::Skip
+            && stream.get_as::<_, NiNode>(stream.roots[0]).is_none()
+        {
+            warn!("Root is not a node, skipping: {abs_path:?}");
+            records.push(MeshRecord {
+                status: Status::Skipped,
+                reason: "non-node-root".into(),
+                ..record
+            });
+            continue;
+        }
+
+        record.wrap_root = needs_root_wrapper(&stream, params.wrap);
+        record.had_no_collision = has_no_collision_tag(&stream);
+
+        let relative_path = with_original_extension(&mesh_path, &weapon.mesh);
+
+        let relative_path = match &args.output_template {
+            Some(template) => match template.render(projectile, &weapon.id, &relative_path) {
+                Ok(path) => path,
+                Err(error) => {
+                    error!("Invalid output path for {:?}: {error}", weapon.id);
+                    records.push(MeshRecord {
+                        status: Status::InvalidOutputPath,
+                        reason: error,
+                        ..record
+                    });
+                    continue;
+                }
+            },
+            None => relative_path.split('/').filter(|c| !c.is_empty()).collect(),
+        };
+
+        // Split meshes keep their name for the first weapon, and the others get
+        // their id appended, unless the template tells them apart already.
+        let relative_path = if split
+            && output_paths.contains(&relative_path.to_string_lossy().to_lowercase())
+        {
+            let suffixed = with_suffix(&relative_path, &format!("_{}", weapon.id.to_lowercase()));
+            warn!("Writing split mesh for {:?} to: {suffixed:?}", weapon.id);
+            suffixed
+        } else {
+            relative_path
+        };
+
+        // Templates may send different meshes to the same file.
+        if !output_paths.insert(relative_path.to_string_lossy().to_lowercase()) {
+            warn!("Skipping {abs_path:?}, another mesh was already written to: {relative_path:?}");
+            records.push(MeshRecord {
+                status: Status::Skipped,
+                reason: "collision".into(),
+                ..record
+            });
+            continue;
+        }
+
+        let mut output_path = output_path.join(relative_path);
+
+        // Keep the flying mesh as is, the stuck variants get their own names.
+        if let Some(suffix) = &args.stuck_suffix {
+            let flying = MeshRecord {
+                output_path: output_path.display().to_string(),
+                offset: 0.0,
+                scale: 1.0,
+                profile: "flying".into(),
+                ..record.clone()
+            };
+
+            if args.only_missing && output_path.exists() {
+                debug!("Output exists, skipping: {output_path:?}");
+                records.push(MeshRecord {
+                    status: Status::Skipped,
+                    reason: "exists".into(),
+                    ..flying
+                });
+            } else {
+                info!("Copying original mesh to: {:?}", output_path);
+
+                let result = if args.dry_run {
+                    Ok(())
+                } else {
+                    std::fs::read(abs_path)
+                        .map_err(|source| PincushionError::Save {
+                            path: output_path.clone(),
+                            source,
+                        })
+                        .and_then(|bytes| write_output(&output_path, &bytes))
+                };
+
+                match result {
+                    Ok(()) if args.dry_run => records.push(MeshRecord {
+                        status: Status::WouldSave,
+                        ..flying
+                    }),
+                    Ok(()) => records.push(flying),
+                    Err(error) => {
+                        error!("{error}");
+                        records.push(MeshRecord {
+                            status: Status::SaveFailed,
+                            reason: error.to_string(),
+                            ..flying
+                        });
+                    }
+                }
+            }
+
+            output_path = with_suffix(&output_path, suffix);
+        }
+
+        // Hashed along with the parameters to tell when outputs are current.
+        let source_bytes = std::fs::read(abs_path).unwrap_or_default();
+
+        // Without profiles there is a single variant using the arguments as is.
+        let variants: Vec<_> = match &args.profiles {
+            Some(profiles) => profiles
+                .iter()
+                .map(|(name, p)| (name.as_str(), Some(p)))
+                .collect(),
+            None => vec![("", None)],
         };
 
-        for child in root.children.clone() {
-            let Some(object) = stream.get_mut(child) else {
+        for (name, profile) in variants {
+            let mut params = params;
+            let mut stream = stream.clone();
+            let mut record = record.clone();
+            let mut output_path = output_path.clone();
+
+            if let Some(profile) = profile {
+                profile.apply(&mut params);
+                output_path = with_suffix(&output_path, &profile.suffix);
+                record.offset = params.offset;
+                record.scale = params.scale;
+                record.profile = name.to_string();
+            }
+
+            if args.only_missing && output_path.exists() {
+                debug!("Output exists, skipping: {output_path:?}");
+                records.push(MeshRecord {
+                    output_path: output_path.display().to_string(),
+                    status: Status::Skipped,
+                    reason: "exists".into(),
+                    ..record
+                });
                 continue;
+            }
+
+            let hash = content_hash(&source_bytes, args, &params);
+
+            // Only loaded when needed, this reads the whole output mesh.
+            let output_is_current = || {
+                load_nif(&output_path)
+                    .is_ok_and(|output| read_marker_tag(&output).and_then(|m| m.hash) == Some(hash))
             };
-            match weapon.data.weapon_type {
-                WeaponType::MarksmanThrown => {
-                    process_throwable(object);
+
+            // Keep what the previous run recorded, the output is still current.
+            if let Some(previous) = previous.get(&output_path.display().to_string().to_lowercase())
+                && previous.record_hash == record.record_hash
+                && matches!(previous.status, Status::Saved | Status::Unprocessed)
+                && output_is_current()
+            {
+                debug!("Weapon record unchanged, skipping: {output_path:?}");
+                records.push(previous.clone());
+                continue;
+            }
+
+            if args.skip_unchanged && output_is_current() {
+                debug!("Output is up to date, skipping: {output_path:?}");
+                records.push(MeshRecord {
+                    output_path: output_path.display().to_string(),
+                    status: Status::Skipped,
+                    reason: "unchanged".into(),
+                    ..record
+                });
+                continue;
+            }
+
+            let before = args.report_graph.as_ref().map(|_| graph::dump(&stream));
+
+            if let Some(reason) = apply_pincushion(&mut stream, args, &params, &mesh_path) {
+                debug!("Nothing to transform ({reason}): {abs_path:?}");
+                record.status = Status::Unprocessed;
+                record.reason = reason.into();
+            }
+
+            insert_marker_hash(&mut stream, hash);
+
+            if args.export_bounds.is_some() {
+                let roots: Vec<_> = stream.roots.iter().map(|root| root.cast()).collect();
+                record.bounds = geometry::bounding_box(&stream, &roots)
+                    .map(|(min, max)| Bounds::new(min.to_array(), max.to_array()));
+            }
+
+            if let Some(dir) = &args.report_graph
+                && let Some(before) = before
+            {
+                write_graph_report(dir, &output_root, &output_path, &before, &stream);
+            }
+
+            record.output_path = output_path.display().to_string();
+
+            // A dry run goes through everything, except for writing.
+            if args.dry_run {
+                info!("Would save modified mesh to: {:?}", output_path);
+                if record.status == Status::Saved {
+                    record.status = Status::WouldSave;
                 }
-                WeaponType::Arrow => {
-                    process_arrow(object, args);
+                records.push(record);
+                continue;
+            }
+
+            info!("Saving modified mesh to: {:?}", output_path);
+
+            if let Err(error) = save_nif(&stream, &output_path) {
+                error!("{error}");
+                record.status = Status::SaveFailed;
+                record.reason = error.to_string();
+                records.push(record);
+                continue;
+            }
+
+            if let Some(hook) = &args.post_hook
+                && let Err(error) = run_command(hook, &[("{path}", &output_path)])
+            {
+                error!("Post hook failed for {output_path:?}: {error}");
+                record.status = Status::HookFailed;
+                record.reason = error;
+            }
+
+            records.push(record);
+        }
+    }
+
+    for (rule, count) in ignored {
+        info!("Ignored {count} meshes by rule {rule}");
+    }
+}
+
+/// Process a single NIF file as the given `--input-type`, without looking at
+/// the game data at all.
+///
+/// The result is written to the output directory under its file name, or to
+/// stdout with `--stdout`.
+///
+fn process_single(args: &Args, path: &Path) {
+    let mut stream = match load_nif(path) {
+        Ok(stream) => stream,
+        Err(error) => fail(error),
+    };
+
+    if stream.roots.is_empty() {
+        warn!("Mesh has no root nodes, skipping: {path:?}");
+        return;
+    }
+
+    if let Err(error) = check_root_count(&stream, path) {
+        fail(error);
+    }
+
+    let file_name = path.file_name().unwrap_or_default();
+    let key = file_name.to_string_lossy().to_lowercase();
+    let params = args.params(args.input_type);
+
+    if let Some(reason) = apply_pincushion(&mut stream, args, &params, &key) {
+        warn!("Nothing to transform ({reason}): {path:?}");
+    }
+
+    let source_bytes = std::fs::read(path).unwrap_or_default();
+    insert_marker_hash(&mut stream, content_hash(&source_bytes, args, &params));
+
+    if args.stdout {
+        let bytes = match stream.save_bytes() {
+            Ok(bytes) => bytes,
+            Err(source) => fail(PincushionError::Save {
+                path: path.to_path_buf(),
+                source,
+            }),
+        };
+        let mut stdout = std::io::stdout().lock();
+        let result = std::io::Write::write_all(&mut stdout, &bytes)
+            .and_then(|_| std::io::Write::flush(&mut stdout));
+        if let Err(error) = result {
+            eprintln!("Failed to write to stdout: {error}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let output_path = args.output_root().join(file_name);
+
+    if args.dry_run {
+        info!("Would save modified mesh to: {output_path:?}");
+        return;
+    }
+
+    info!("Saving modified mesh to: {output_path:?}");
+
+    if let Err(error) = save_nif(&stream, &output_path) {
+        fail(error);
+    }
+}
+
+/// Process `path` repeatedly, prompting for a new offset and scale each time.
+///
+/// Returns once the current values are accepted with an empty line, exits if
+/// the user quits. Does nothing unless stdin is a terminal, the values given
+/// on the command line are then used for the batch as they are.
+///
+fn tune_interactively(args: &mut Args, path: &Path) {
+    if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        warn!("Not prompting for --interactive, stdin is not a terminal");
+        return;
+    }
+
+    let projectile = args.input_type;
+    let file_name = path.file_name().unwrap_or_default();
+
+    loop {
+        process_single(args, path);
+        println!("Preview: {}", args.output_root().join(file_name).display());
+
+        let params = args.params(projectile);
+        let mut offset = params.offset;
+        let mut scale = params.scale;
+        if projectile == Projectile::Thrown && !args.thrown_as_arrow {
+            print!("Offset [{offset}] (empty to accept, q to quit): ");
+        } else {
+            print!("Offset and scale [{offset} {scale}] (empty to accept, q to quit): ");
+        }
+        std::io::Write::flush(&mut std::io::stdout()).unwrap();
+
+        let mut answer = String::new();
+        match std::io::stdin().read_line(&mut answer) {
+            Ok(0) | Err(_) => std::process::exit(0),
+            Ok(_) => {}
+        }
+
+        let mut values = answer.split_whitespace();
+        match values.next() {
+            None => break,
+            Some(value) if value.eq_ignore_ascii_case("q") => std::process::exit(0),
+            Some(value) => match value.parse() {
+                Ok(value) => offset = value,
+                Err(_) => {
+                    eprintln!("Invalid offset: {value}");
+                    continue;
                 }
-                WeaponType::Bolt => {
-                    process_bolt(object, args);
+            },
+        }
+        if let Some(value) = values.next() {
+            match value.parse() {
+                Ok(value) => scale = value,
+                Err(_) => {
+                    eprintln!("Invalid scale: {value}");
+                    continue;
                 }
-                _ => {}
             }
         }
 
-        insert_no_collision_tag(&mut stream);
+        args.set_transform(projectile, offset, scale);
+    }
+
+    let params = args.params(projectile);
+    info!(
+        "Processing all meshes with offset {} and scale {}",
+        params.offset, params.scale
+    );
+}
+
+/// Write the scene graph of a mesh before and after processing.
+///
+/// Reports mirror the layout of the output directory, with a ".txt" suffix.
+///
+fn write_graph_report(
+    dir: &Path,
+    output_root: &Path,
+    output_path: &Path,
+    before: &str,
+    stream: &NiStream,
+) {
+    let relative = output_path.strip_prefix(output_root).unwrap_or(output_path);
 
-        let output_path = output_path.join(mesh_path);
+    let mut file_name = relative.as_os_str().to_os_string();
+    file_name.push(".txt");
+    let path = dir.join(file_name);
 
-        println!("Saving modified mesh to: {:?}", output_path);
+    let text = format!("# Before\n{before}\n# After\n{}", graph::dump(stream));
 
-        std::fs::create_dir_all(output_path.parent().unwrap()).unwrap();
-        stream.save_path(&output_path).unwrap();
+    let result =
+        std::fs::create_dir_all(path.parent().unwrap()).and_then(|_| std::fs::write(&path, text));
+
+    if let Err(error) = result {
+        warn!("Failed to write graph report {path:?}: {error}");
     }
 }
 
-/// Generate pincushion projectile NIFs for OpenMW
-#[derive(Parser, Debug)]
-#[command(version, about)]
-struct Args {
-    /// Arrow offset
-    #[arg(long, required = true)]
-    arrow_offset: f32,
-
-    /// Arrow scale
-    #[arg(long, required = true)]
-    arrow_scale: f32,
+/// Run a user command, replacing each placeholder with its path.
+///
+/// Used for the post-processing hook and the gallery renderer.
+///
+fn run_command(command: &str, placeholders: &[(&str, &Path)]) -> Result<(), String> {
+    let mut parts = command.split_whitespace().map(|part| {
+        placeholders
+            .iter()
+            .fold(part.to_string(), |part, (placeholder, path)| {
+                part.replace(placeholder, &path.to_string_lossy())
+            })
+    });
 
-    /// Bolt offset
-    #[arg(long, required = true)]
-    bolt_offset: f32,
+    let program = parts.next().ok_or("empty command")?;
+    let status = std::process::Command::new(&program)
+        .args(parts)
+        .status()
+        .map_err(|e| format!("{program}: {e}"))?;
 
-    /// Bolt scale
-    #[arg(long, required = true)]
-    bolt_scale: f32,
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(format!("exit code {code}")),
+        None => Err("terminated by signal".into()),
+    }
 }
 
-fn main() {
-    let args = Args::parse();
+/// Render a thumbnail of every generated mesh and index them in `dir`.
+///
+/// Rendering failures are only warned about, the mesh is still listed.
+///
+fn write_gallery(args: &Args, dir: &Path, renderer: &str, records: &[MeshRecord]) {
+    let output_root = args.output_root();
+    let mut entries = vec![];
+
+    for record in records {
+        if !matches!(
+            record.status,
+            Status::Saved | Status::Unprocessed | Status::HookFailed
+        ) {
+            continue;
+        }
 
-    let config = OpenMWConfiguration::new(None).unwrap();
+        let output_path = Path::new(&record.output_path);
+        let relative = output_path
+            .strip_prefix(&output_root)
+            .unwrap_or(output_path);
+        let thumbnail = relative.with_extension("png");
+        let path = dir.join(&thumbnail);
 
-    let vfs = VFS::from_directories(config.data_directories(), None);
+        // Don't mistake the thumbnail of an earlier run for a new one.
+        let _ = std::fs::remove_file(&path);
+        let result = std::fs::create_dir_all(path.parent().unwrap())
+            .map_err(|e| e.to_string())
+            .and_then(|_| {
+                run_command(renderer, &[("{path}", output_path), ("{thumbnail}", &path)])
+            });
 
-    for file in config.content_files() {
-        let path = Path::new(&file);
-        if let Some(extension) = path.extension()
-            && let bytes = extension.as_encoded_bytes()
-            && (bytes.eq_ignore_ascii_case(b"esp")
-                || bytes.eq_ignore_ascii_case(b"esm")
-                || bytes.eq_ignore_ascii_case(b"omwaddon")
-                || bytes.eq_ignore_ascii_case(b"omwgam"))
-        {
-            if let Some(vfs_file) = vfs.get_file(file) {
-                process_plugin(&args, &vfs, vfs_file.path());
+        let rendered = match result {
+            Ok(()) if path.is_file() => true,
+            Ok(()) => {
+                warn!("Renderer wrote no thumbnail for {output_path:?}: {path:?}");
+                false
             }
-        }
+            Err(error) => {
+                warn!("Failed to render thumbnail for {output_path:?}: {error}");
+                false
+            }
+        };
+
+        entries.push(GalleryEntry {
+            weapon_id: record.weapon_id.clone(),
+            profile: record.profile.clone(),
+            output_path: relative.to_string_lossy().replace('\\', "/"),
+            thumbnail: rendered.then(|| thumbnail.to_string_lossy().replace('\\', "/")),
+            offset: record.offset,
+            scale: record.scale,
+        });
+    }
+
+    let path = dir.join("index.html");
+    let result = std::fs::create_dir_all(dir).and_then(|_| report::write_gallery(&path, &entries));
+    match result {
+        Ok(()) => info!("Wrote gallery of {} meshes: {path:?}", entries.len()),
+        Err(error) => eprintln!("Failed to write gallery {path:?}: {error}"),
+    }
+}
+
+/// Save the transform for `--input-type` on its own, see `template_stream`.
+fn save_template(args: &Args, path: &Path) {
+    let params = args.params(args.input_type);
+    let (stream, reason) = template_stream(args, &params);
+
+    if let Some(reason) = reason {
+        warn!("Nothing to transform in the template ({reason})");
+    }
+
+    if args.dry_run {
+        info!("Would save template to: {path:?}");
+        return;
+    }
+
+    info!("Saving template to: {path:?}");
+
+    if let Err(error) = save_nif(&stream, path) {
+        fail(error);
+    }
+}
+
+/// Print the transform applied to the first child of a mesh's root node.
+///
+/// This is the node we transform when processing, so its values can be fed
+/// back into the command line to reproduce the same pose. The arguments are
+/// those of the projectile type recorded in our marker, meshes without one
+/// are taken as a throwable if flipped and as an arrow otherwise.
+///
+fn extract_transform(path: &Path) {
+    let stream = match load_nif(path) {
+        Ok(stream) => stream,
+        Err(error) => fail(error),
+    };
+
+    let no_transform = |reason| PincushionError::NoTransform {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let Some(root) = stream
+        .roots
+        .first()
+        .and_then(|&root| stream.get_as::<_, NiNode>(root))
+    else {
+        fail(no_transform("root is not a NiNode"));
+    };
+
+    let Some(object) = root.children.first().and_then(|&child| stream.get(child)) else {
+        fail(no_transform("root has no children"));
+    };
+
+    let (t, r, s) = (object.translation, object.rotation, object.scale);
+
+    println!("Translation: [{}, {}, {}]", t.x, t.y, t.z);
+    println!("Rotation: [{:?}, {:?}, {:?}]", r.x_axis, r.y_axis, r.z_axis);
+    println!("Scale: {s}");
+
+    // Our own meshes record exactly what was applied to them.
+    let (offset, scale, projectile) = match read_marker_tag(&stream) {
+        Some(Marker {
+            offset,
+            scale,
+            projectile,
+            ..
+        }) => {
+            println!(
+                "Generated by {}: offset={offset} scale={scale}",
+                crate_name!()
+            );
+            (offset, scale, projectile)
+        }
+        None => (Vec3::new(0.0, t.y, 0.0), s, None),
+    };
+
+    let projectile = projectile.unwrap_or(if scale < 0.0 {
+        Projectile::Thrown
+    } else {
+        Projectile::Arrow
+    });
+
+    let axis = Axis::dominant(offset);
+    let amount = offset.dot(axis.unit());
+    let axis = axis.to_possible_value().unwrap();
+    let axis = axis.get_name();
+
+    // The scale of throwables is fixed, there is no argument for it.
+    match projectile {
+        Projectile::Arrow => {
+            println!("Arguments: --arrow-offset {amount} --arrow-axis {axis} --arrow-scale {scale}")
+        }
+        Projectile::Bolt => {
+            println!("Arguments: --bolt-offset {amount} --bolt-axis {axis} --bolt-scale {scale}")
+        }
+        Projectile::Thrown => {
+            println!("Arguments: --throwable-offset {amount} --throwable-axis {axis}")
+        }
+    }
+}
+
+/// Recursively collect all NIF files within a directory.
+fn find_nif_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_nif_files(&path, files);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("nif"))
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// Delete generated meshes from the output directory.
+///
+/// Only files carrying our marker are removed, anything else that happens to
+/// live in the output directory is left alone.
+///
+fn clean(output_root: &Path, yes: bool) {
+    let mut files = vec![];
+    find_nif_files(output_root, &mut files);
+
+    files.retain(|path| load_nif(path).is_ok_and(|stream| has_marker_tag(&stream)));
+
+    if files.is_empty() {
+        println!("No generated meshes found in: {output_root:?}");
+        return;
+    }
+
+    for path in &files {
+        println!("{path:?}");
+    }
+
+    if !yes {
+        print!("Delete {} generated meshes? [y/N] ", files.len());
+        std::io::Write::flush(&mut std::io::stdout()).unwrap();
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).is_err()
+            || !answer.trim().eq_ignore_ascii_case("y")
+        {
+            println!("Aborted");
+            return;
+        }
+    }
+
+    for path in files {
+        if let Err(error) = std::fs::remove_file(&path) {
+            eprintln!("Failed to remove {path:?}: {error}");
+        }
+    }
+}
+
+/// Extensions of content files that may contain weapon records.
+///
+/// OpenMW also accepts `.omwscripts` content files, but those only list Lua
+/// scripts and can't be parsed as plugins.
+///
+const CONTENT_EXTENSIONS: [&str; 4] = ["esp", "esm", "omwaddon", "omwgam"];
+
+/// Whether a content file is a plugin we can load weapons from.
+fn is_content_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        CONTENT_EXTENSIONS
+            .iter()
+            .any(|known| extension.eq_ignore_ascii_case(known))
+    })
+}
+
+/// Load the weapons of all active content files, keyed by lowercased id.
+fn load_content_weapons(
+    args: &Args,
+    config: &OpenMWConfiguration,
+    vfs: &VFS,
+) -> HashMap<String, Weapon> {
+    // Merge the weapons of all content files in load order. The game files
+    // and their masters come first, so records they define are inherited by
+    // any addon that doesn't override them, while overrides take precedence.
+    let mut weapons = HashMap::new();
+    let mut origins = HashMap::new();
+    let mut malformed = 0;
+    let mut conflicts = 0;
+    let mut no_weapons = 0;
+    let mut no_projectiles = 0;
+    let mut failed = 0;
+
+    for file in config.content_files() {
+        if is_content_file(Path::new(&file))
+            && let Some(vfs_file) = vfs.get_file(file)
+        {
+            let counts = load_weapons(
+                vfs_file.path(),
+                &mut weapons,
+                &mut origins,
+                args.conflict_policy,
+            );
+            if counts.failed {
+                failed += 1;
+            } else if counts.weapons == 0 {
+                info!("No weapon records in: {file}");
+                no_weapons += 1;
+            } else if counts.projectiles == 0 {
+                info!(
+                    "No projectiles among {} weapon records in: {file}",
+                    counts.weapons
+                );
+                no_projectiles += 1;
+            }
+            malformed += counts.malformed;
+            conflicts += counts.conflicts;
+        }
+    }
+
+    if malformed > 0 {
+        warn!("Skipped {malformed} malformed weapon records");
+    }
+
+    // Plugins are parsed as a whole, one bad record loses all of them.
+    if failed > 0 {
+        warn!("Failed to parse {failed} content files, none of their weapons were loaded");
+    }
+
+    if conflicts > 0 {
+        if args.conflict_policy == ConflictPolicy::Error {
+            eprintln!("Found {conflicts} weapons with duplicate ids, see --conflict-policy");
+            std::process::exit(1);
+        }
+        info!("Resolved {conflicts} weapons with duplicate ids, see --conflict-policy");
+    }
+
+    if no_weapons + no_projectiles > 0 {
+        info!("Content files without weapons: {no_weapons}, without projectiles: {no_projectiles}");
+    }
+
+    weapons
+}
+
+fn process_content_files(
+    args: &Args,
+    config: &OpenMWConfiguration,
+    vfs: &VFS,
+    changed: Option<&HashSet<PathBuf>>,
+    records: &mut Vec<MeshRecord>,
+) {
+    let weapons = load_content_weapons(args, config, vfs);
+
+    if let Some(path) = &args.shared_mesh_report {
+        let entries = shared_meshes(args, &weapons);
+        if let Err(error) = report::write_shared_meshes(path, &entries) {
+            eprintln!("Failed to write shared mesh report {path:?}: {error}");
+        }
+    }
+
+    let ignore = IgnoreRules::from_directories(&config.data_directories());
+    let archives = open_archives(config);
+    process_weapons(args, vfs, &archives, &weapons, &ignore, changed, records);
+}
+
+/// Every projectile mesh with the weapons that use it, sorted by path.
+fn shared_meshes(args: &Args, weapons: &HashMap<String, Weapon>) -> Vec<SharedMesh> {
+    let mut meshes: BTreeMap<_, (BTreeSet<_>, BTreeSet<_>)> = BTreeMap::new();
+
+    for (id, weapon) in weapons {
+        if projectile_type(args, id, weapon).is_some() {
+            let (ids, types) = meshes.entry(mesh_key(weapon)).or_default();
+            ids.insert(weapon.id.clone());
+            types.insert(format!("{:?}", weapon.data.weapon_type));
+        }
+    }
+
+    meshes
+        .into_iter()
+        .map(|(mesh_path, (ids, types))| SharedMesh {
+            mesh_path: format!("meshes/{mesh_path}"),
+            weapon_ids: ids.into_iter().collect(),
+            weapon_types: types.into_iter().collect(),
+        })
+        .collect()
+}
+
+/// Write the mesh of every projectile weapon, without transforming anything.
+fn export_mapping(args: &Args, config: &OpenMWConfiguration, vfs: &VFS, path: &Path) {
+    let weapons = load_content_weapons(args, config, vfs);
+    let archives = open_archives(config);
+
+    let mut entries: Vec<_> = weapons
+        .iter()
+        .filter(|(id, weapon)| projectile_type(args, id, weapon).is_some())
+        .map(|(_, weapon)| {
+            let mesh_path = format!("meshes/{}", mesh_key(weapon));
+            MappingEntry {
+                weapon_id: weapon.id.clone(),
+                weapon_type: format!("{:?}", weapon.data.weapon_type),
+                resolved_path: locate_mesh(vfs, &archives, &mesh_path)
+                    .map(|location| location.origin(&mesh_path).display().to_string()),
+                mesh_path,
+            }
+        })
+        .collect();
+
+    entries.sort_by(|a, b| a.weapon_id.cmp(&b.weapon_id));
+
+    if let Err(error) = report::write_mapping(path, &entries) {
+        eprintln!("Failed to write mapping {path:?}: {error}");
+        std::process::exit(1);
+    }
+
+    println!("Exported {} weapons to: {path:?}", entries.len());
+}
+
+/// The mesh field each weapon should be given to use its generated mesh.
+///
+/// Fields use backslashes and are relative to the meshes directory, like in
+/// the original game data, and are listed for every mesh that was or would be
+/// written.
+///
+fn record_entries(output_root: &Path, records: &[MeshRecord]) -> Vec<RecordEntry> {
+    records
+        .iter()
+        .filter(|record| !record.output_path.is_empty() && record.status != Status::SaveFailed)
+        .map(|record| {
+            let output_path = Path::new(&record.output_path);
+            let relative = output_path.strip_prefix(output_root).unwrap_or(output_path);
+            RecordEntry {
+                weapon_id: record.weapon_id.clone(),
+                profile: record.profile.clone(),
+                current_mesh: record.mesh.clone(),
+                suggested_mesh: relative.to_string_lossy().replace('/', "\\"),
+            }
+        })
+        .collect()
+}
+
+/// Print the differences between two manifests.
+fn diff_manifest(old: &Path, new: &Path) {
+    let read = |path: &Path| {
+        report::read_manifest(path).unwrap_or_else(|error| {
+            eprintln!("Failed to read manifest {path:?}: {error}");
+            std::process::exit(1);
+        })
+    };
+
+    let diff = ManifestDiff::new(&read(old), &read(new));
+    diff.print();
+
+    if !diff.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Open the archives of the config, see `Archives`.
+fn open_archives(config: &OpenMWConfiguration) -> Archives {
+    Archives::open(&config.fallback_archives(), &config.data_directories())
+}
+
+/// Where a mesh was found, see `locate_mesh`.
+enum MeshLocation<'a> {
+    /// A loose file, or whatever else the VFS has at its path.
+    Loose(PathBuf),
+    /// A file within the archive at this path.
+    Archived(&'a Path),
+}
+
+/// Find a mesh, as a loose file or within an archive.
+///
+/// Loose files take precedence over archives, like in the engine. A VFS entry
+/// that isn't a loose file is returned as is if no archive has the mesh
+/// either, so the caller can report it.
+///
+fn locate_mesh<'a>(vfs: &VFS, archives: &'a Archives, mesh_path: &str) -> Option<MeshLocation<'a>> {
+    // Archived entries point at the archive itself, which is no mesh.
+    let loose = vfs
+        .get_file(mesh_path)
+        .map(|file| file.path().to_path_buf())
+        .filter(|path| !archives.contains(path));
+
+    match loose {
+        Some(path) if path.is_file() => Some(MeshLocation::Loose(path)),
+        loose => archives
+            .find(mesh_path)
+            .map(MeshLocation::Archived)
+            .or(loose.map(MeshLocation::Loose)),
+    }
+}
+
+impl MeshLocation<'_> {
+    /// A path to report the mesh by, archived files are listed as if their
+    /// archive was a directory.
+    fn origin(&self, mesh_path: &str) -> PathBuf {
+        match self {
+            Self::Loose(path) => path.clone(),
+            Self::Archived(archive) => archive.join(mesh_path),
+        }
+    }
+}
+
+/// A mesh found by `resolve_mesh`.
+struct ResolvedMesh {
+    /// The file to read, extracted to a temporary directory if archived.
+    path: PathBuf,
+    /// Where the mesh comes from, see `MeshLocation::origin`.
+    origin: PathBuf,
+}
+
+/// Find a mesh on disk, extracting it if it's archived. See `locate_mesh`.
+fn resolve_mesh(
+    vfs: &VFS,
+    archives: &Archives,
+    mesh_path: &str,
+) -> Result<ResolvedMesh, PincushionError> {
+    let not_found = || PincushionError::MeshNotFound {
+        path: mesh_path.into(),
+    };
+    let location = locate_mesh(vfs, archives, mesh_path).ok_or_else(not_found)?;
+    let origin = location.origin(mesh_path);
+    let path = match location {
+        MeshLocation::Loose(path) => path,
+        MeshLocation::Archived(_) => archives.extract(mesh_path).ok_or_else(not_found)?,
+    };
+    Ok(ResolvedMesh { path, origin })
+}
+
+/// Build the VFS the way OpenMW resolves files.
+///
+/// Loose files in later data directories override those in earlier ones, and
+/// any loose file overrides the archives. Among the archives listed with
+/// `fallback-archive=`, later ones override earlier ones.
+///
+fn build_vfs(config: &OpenMWConfiguration) -> VFS {
+    let archives = config.fallback_archives();
+    VFS::from_directories(config.data_directories(), Some(archives))
+}
+
+/// Watch the data directories and reprocess whenever something changes.
+///
+/// Changed meshes are reprocessed individually, while any change to an active
+/// content file reprocesses everything since weapon records may have moved.
+/// After each pass the records are compared against those of the previous
+/// one, starting with `records` of the initial run, and the differences are
+/// printed like `diff-manifest` does.
+///
+fn watch(args: &Args, config: &OpenMWConfiguration, mut records: Vec<MeshRecord>) {
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    let Ok(mut watcher) = notify::recommended_watcher(sender) else {
+        eprintln!("Failed to create file watcher");
+        return;
+    };
+
+    for dir in config.data_directories() {
+        if watcher.watch(&dir, RecursiveMode::Recursive).is_err() {
+            eprintln!("Failed to watch data directory: {dir:?}");
+        }
+    }
+
+    // Don't react to our own output if it lives inside a data directory.
+    let output_root = args.output_root();
+
+    let content_files: HashSet<String> = config
+        .content_files()
+        .iter()
+        .map(|file| file.to_lowercase())
+        .collect();
+
+    println!("Watching for changes...");
+
+    while !interrupted() {
+        let first = match receiver.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        // Collect any events that follow in quick succession.
+        let mut events = vec![first];
+        while let Ok(event) = receiver.recv_timeout(WATCH_DEBOUNCE) {
+            events.push(event);
+        }
+
+        let mut meshes = HashSet::new();
+        let mut plugins_changed = false;
+
+        for path in events.into_iter().flatten().flat_map(|event| event.paths) {
+            if path.starts_with(&output_root) {
+                continue;
+            }
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if content_files.contains(&file_name.to_lowercase()) {
+                println!("Changed plugin: {path:?}");
+                plugins_changed = true;
+            } else if file_name.to_lowercase().ends_with(".nif") {
+                println!("Changed mesh: {path:?}");
+                meshes.insert(path.canonicalize().unwrap_or(path));
+            }
+        }
+
+        if !plugins_changed && meshes.is_empty() {
+            continue;
+        }
+
+        // Rebuild the VFS so that added or removed files are picked up.
+        let vfs = build_vfs(config);
+
+        let changed = (!plugins_changed).then_some(&meshes);
+        let mut new_records = vec![];
+        process_content_files(args, config, &vfs, changed, &mut new_records);
+
+        // Only the changed meshes have new records, the rest are as before.
+        if changed.is_some() {
+            new_records = report::merge(records.clone(), new_records);
+        }
+
+        let diff = ManifestDiff::new(&records, &new_records);
+        if diff.is_empty() {
+            println!("No changes to the output");
+        } else {
+            diff.print();
+        }
+
+        records = new_records;
+    }
+}
+
+fn write_manifest(args: &Args, path: &Path, mut records: Vec<MeshRecord>) {
+    if args.manifest_append && path.exists() {
+        match report::read_manifest(path) {
+            Ok(existing) => records = report::merge(existing, records),
+            Err(error) => warn!("Ignoring unreadable manifest {path:?}: {error}"),
+        }
+    }
+    if let Err(error) = report::write_manifest(path, &records) {
+        eprintln!("Failed to write manifest {path:?}: {error}");
+    }
+}
+
+/// Print the openmw.cfg lines that add the output directory to the VFS.
+///
+/// Paths are quoted and escaped the same way OpenMW writes them, with `&` as
+/// the escape character.
+///
+fn print_cfg_snippet(output_root: &Path) {
+    println!("# Add to openmw.cfg after your other data= lines:");
+    println!("{}", cfg_data_line(output_root));
+}
+
+/// The openmw.cfg line adding a directory to the VFS.
+fn cfg_data_line(dir: &Path) -> String {
+    let escaped = dir
+        .display()
+        .to_string()
+        .replace('&', "&&")
+        .replace('"', "&\"");
+    format!("data=\"{escaped}\"")
+}
+
+/// The directory of an openmw.cfg data= line, undoing the quoting.
+fn parse_cfg_data_line(line: &str) -> Option<PathBuf> {
+    let value = line.trim().strip_prefix("data")?.trim_start();
+    let value = value.strip_prefix('=')?.trim();
+    let Some(quoted) = value.strip_prefix('"') else {
+        return Some(PathBuf::from(value));
+    };
+    let mut path = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => path.extend(chars.next()),
+            '"' => break,
+            c => path.push(c),
+        }
+    }
+    Some(PathBuf::from(path))
+}
+
+/// The user's openmw.cfg of the loaded configuration, the last one in the
+/// chain of configurations that OpenMW reads and the one it writes to.
+fn user_config_path(config: &OpenMWConfiguration) -> PathBuf {
+    let path = config.user_config_path().to_path_buf();
+    if path.is_dir() {
+        path.join("openmw.cfg")
+    } else {
+        path
+    }
+}
+
+/// A path made absolute and canonical where possible, for comparisons.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Append the output directory to the user's openmw.cfg, unless listed.
+fn register_output(output_root: &Path, config: &OpenMWConfiguration) {
+    let path = user_config_path(config);
+
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) => {
+            eprintln!("Failed to read {path:?}: {error}");
+            return;
+        }
+    };
+
+    // Relative data= entries are relative to the directory of their file.
+    let cfg_dir = path.parent().unwrap_or(Path::new(""));
+    let output_root = canonical(output_root);
+    let registered = text
+        .lines()
+        .filter_map(parse_cfg_data_line)
+        .any(|dir| canonical(&cfg_dir.join(dir)) == output_root);
+    if registered {
+        info!("Output directory is already registered in {path:?}");
+        return;
+    }
+
+    let mut new_text = text.clone();
+    if !new_text.is_empty() && !new_text.ends_with('\n') {
+        new_text.push('\n');
+    }
+    new_text += &cfg_data_line(&output_root);
+    new_text.push('\n');
+
+    let backup = path.with_extension("cfg.bak");
+    let result =
+        std::fs::write(&backup, &text).and_then(|_| write_atomic(&path, new_text.as_bytes()));
+    match result {
+        Ok(()) => {
+            info!("Registered output directory in {path:?}, previous file kept as {backup:?}")
+        }
+        Err(error) => eprintln!("Failed to update {path:?}: {error}"),
+    }
+}
+
+/// Log the content files in load order, with where each was found.
+///
+/// One plugin name per line after the index, so the list can be compared
+/// against or pasted into load order tools.
+///
+fn echo_load_order(config: &OpenMWConfiguration, vfs: &VFS) {
+    info!("Load order:");
+    for (index, file) in config.content_files().iter().enumerate() {
+        match vfs.get_file(file) {
+            Some(vfs_file) => info!("{index:03} {file} ({})", vfs_file.path().display()),
+            None => info!("{index:03} {file} (not found)"),
+        }
+    }
+}
+
+/// List the data directories, archives and content files of the config, and
+/// whether they can be found and read.
+fn validate_config(config: &OpenMWConfiguration, vfs: &VFS) {
+    let mut problems = 0;
+
+    let data_directories = config.data_directories();
+
+    println!("Data directories:");
+    for dir in data_directories.iter() {
+        let status = if !dir.exists() {
+            "missing"
+        } else if !dir.is_dir() {
+            "not a directory"
+        } else if std::fs::read_dir(dir).is_err() {
+            "unreadable"
+        } else {
+            "ok"
+        };
+        if status != "ok" {
+            problems += 1;
+        }
+        println!("    [{status}] {}", dir.display());
+    }
+
+    println!("Archives:");
+    for archive in config.fallback_archives() {
+        let found = data_directories
+            .iter()
+            .map(|dir| dir.join(&archive))
+            .rfind(|path| path.is_file());
+        match found {
+            Some(path) => println!("    [ok] {archive} ({})", path.display()),
+            None => {
+                problems += 1;
+                println!("    [missing] {archive}");
+            }
+        }
+    }
+
+    println!("Content files:");
+    for file in config.content_files() {
+        let path = vfs.get_file(file).map(|file| file.path().to_path_buf());
+        match path {
+            Some(path) if std::fs::File::open(&path).is_ok() => {
+                println!("    [ok] {file} ({})", path.display());
+            }
+            Some(path) => {
+                problems += 1;
+                println!("    [unreadable] {file} ({})", path.display());
+            }
+            None => {
+                problems += 1;
+                println!("    [missing] {file}");
+            }
+        }
+    }
+
+    if !data_directories.iter().any(|dir| dir.is_dir()) {
+        println!("No usable data directories, check the data= entries in your openmw.cfg");
+        std::process::exit(1);
+    }
+
+    if problems > 0 {
+        println!("Found {problems} problems");
+        std::process::exit(1);
+    }
+
+    println!("Configuration looks good");
+}
+
+/// Print how many projectile meshes would be generated, by type.
+///
+/// Only the content files are read, no mesh is looked up or parsed. Meshes
+/// are gathered and filtered as by `process_weapons`, so split meshes count
+/// once per weapon and each profile counts as a mesh of its own.
+///
+fn count_only(args: &Args, config: &OpenMWConfiguration, vfs: &VFS) {
+    let weapons = load_content_weapons(args, config, vfs);
+    let ignore = IgnoreRules::from_directories(&config.data_directories());
+
+    let projectiles: Vec<_> = gather_projectiles(args, &weapons)
+        .projectiles
+        .into_iter()
+        .filter(|(mesh_path, ..)| ignore.matches(mesh_path).is_none())
+        .map(|(_, _, projectile, ..)| projectile)
+        .collect();
+
+    let variants = args.profiles.as_ref().map_or(1, |profiles| profiles.len());
+    let count = |projectile| {
+        let meshes = projectiles.iter().filter(|&&p| p == projectile).count();
+        meshes * variants
+    };
+
+    println!(
+        "{} (arrow={} bolt={} thrown={})",
+        projectiles.len() * variants,
+        count(Projectile::Arrow),
+        count(Projectile::Bolt),
+        count(Projectile::Thrown),
+    );
+}
+
+/// Report the state of every projectile mesh in the load order.
+fn audit(args: &Args, config: &OpenMWConfiguration, vfs: &VFS, json: Option<&Path>) {
+    let weapons = load_content_weapons(args, config, vfs);
+
+    let meshes: BTreeSet<_> = weapons
+        .iter()
+        .filter(|(id, weapon)| projectile_type(args, id, weapon).is_some())
+        .map(|(_, weapon)| format!("meshes/{}", mesh_key(weapon)))
+        .collect();
+
+    let mut report = AuditReport {
+        projectiles: meshes.len(),
+        ..Default::default()
+    };
+
+    let archives = open_archives(config);
+
+    for mesh_path in meshes {
+        let Ok(resolved) = resolve_mesh(vfs, &archives, &mesh_path) else {
+            report.not_found.push(mesh_path);
+            continue;
+        };
+        let path = resolved.path.as_path();
+        if !path.is_file() {
+            report.not_a_file.push(mesh_path);
+            continue;
+        }
+        let Ok(stream) = load_nif(path) else {
+            if has_nif_header(path) {
+                report.parse_failed.push(mesh_path);
+            } else {
+                report.not_nif.push(mesh_path);
+            }
+            continue;
+        };
+        if stream.roots.len() != 1 {
+            report.invalid_root_count.push(mesh_path);
+        } else if has_no_collision_tag(&stream) {
+            report.no_collision.push(mesh_path);
+        }
+    }
+
+    report.print();
+
+    if let Some(path) = json
+        && let Err(error) = report::write_audit(path, &report)
+    {
+        eprintln!("Failed to write audit report {path:?}: {error}");
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let mut args = Args::parse();
+
+    let log_file = args.log_file.as_deref();
+
+    // Keep stdout clean when it carries the generated mesh.
+    let result = if args.summary_only {
+        logger::init_quiet(log_file, args.stdout)
+    } else {
+        logger::init(args.verbose, log_file, args.stdout)
+    };
+
+    if let Err(error) = result {
+        eprintln!("Failed to create log file {log_file:?}: {error}");
+        std::process::exit(1);
+    }
+
+    logger::write_file(format_args!("{} {}", crate_name!(), crate_version!()));
+    logger::write_file(format_args!("{args:#?}"));
+
+    match &args.command {
+        Some(Command::Clean { yes }) => return clean(&args.output_root(), *yes),
+        Some(Command::ExtractTransform { path }) => return extract_transform(path),
+        Some(Command::Bench {
+            dir,
+            iterations,
+            projectile,
+        }) => return bench::bench(&args, dir, *iterations, *projectile),
+        Some(Command::Selftest) => return selftest::selftest(),
+        Some(Command::DiffManifest { old, new }) => return diff_manifest(old, new),
+        Some(Command::ExportMapping { .. } | Command::Audit { .. }) | None => {}
+    }
+
+    if let Some(path) = &args.save_template {
+        return save_template(&args, path);
+    }
+
+    if let Some(path) = &args.input_nif
+        && !args.interactive
+    {
+        return process_single(&args, path);
+    }
+
+    let config = match OpenMWConfiguration::new(args.openmw_cfg.clone()) {
+        Ok(config) => config,
+        Err(error) => fail(PincushionError::ConfigLoad {
+            reason: error.to_string(),
+        }),
+    };
+
+    for dir in config.data_directories() {
+        logger::write_file(format_args!("data={dir:?}"));
+    }
+    for file in config.content_files() {
+        logger::write_file(format_args!("content={file}"));
+    }
+
+    let vfs = build_vfs(&config);
+
+    if args.echo_load_order {
+        echo_load_order(&config, &vfs);
+    }
+
+    if args.validate_config {
+        return validate_config(&config, &vfs);
+    }
+
+    // Without any data directories every lookup would fail individually.
+    if !config.data_directories().iter().any(|dir| dir.is_dir()) {
+        fail(PincushionError::VfsBuild {
+            reason: "no usable data directories found, the OpenMW configuration looks empty \
+                     or misconfigured. Check the data= entries in your openmw.cfg."
+                .into(),
+        });
+    }
+
+    match &args.command {
+        Some(Command::ExportMapping { path }) => return export_mapping(&args, &config, &vfs, path),
+        Some(Command::Audit { json }) => return audit(&args, &config, &vfs, json.as_deref()),
+        _ => {}
+    }
+
+    if args.count_only {
+        return count_only(&args, &config, &vfs);
+    }
+
+    if args.interactive
+        && let Some(path) = args.input_nif.clone()
+    {
+        tune_interactively(&mut args, &path);
+    }
+
+    // Finish the mesh in flight rather than dying halfway, a second Ctrl-C
+    // still exits right away.
+    let handler = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    });
+    if let Err(error) = handler {
+        warn!("Failed to install Ctrl-C handler: {error}");
+    }
+
+    // Create the output up front so the path we log is the real one.
+    if !args.dry_run
+        && let Err(error) = std::fs::create_dir_all(args.output_root())
+    {
+        eprintln!(
+            "Failed to create output directory {:?}: {error}",
+            args.output_root()
+        );
+        std::process::exit(1);
+    }
+    info!("Output directory: {}", args.output_root().display());
+
+    let mut records = vec![];
+
+    process_content_files(&args, &config, &vfs, None, &mut records);
+
+    if let Some(path) = &args.manifest {
+        write_manifest(&args, path, records.clone());
+    }
+
+    if let Some(path) = &args.plan
+        && let Err(error) = report::write_json(path, &records)
+    {
+        eprintln!("Failed to write plan {path:?}: {error}");
+    }
+
+    if let Some(path) = &args.export_bounds {
+        let entries: Vec<_> = records
+            .iter()
+            .filter_map(|record| {
+                Some(BoundsEntry {
+                    output_path: record.output_path.clone(),
+                    bounds: record.bounds?,
+                })
+            })
+            .collect();
+        if let Err(error) = report::write_bounds(path, &entries) {
+            eprintln!("Failed to write bounds {path:?}: {error}");
+        }
+    }
+
+    if let Some(dir) = &args.gallery
+        && let Some(renderer) = &args.gallery_renderer
+    {
+        write_gallery(&args, dir, renderer, &records);
+    }
+
+    if args.record_report || args.record_report_csv.is_some() {
+        let entries = record_entries(&args.output_root(), &records);
+        if args.record_report {
+            RecordEntry::print_table(&entries);
+        }
+        if let Some(path) = &args.record_report_csv
+            && let Err(error) = report::write_record_csv(path, &entries)
+        {
+            eprintln!("Failed to write record report {path:?}: {error}");
+        }
+    }
+
+    if let Some(path) = &args.csv
+        && let Err(error) = report::write_csv(path, &records)
+    {
+        eprintln!("Failed to write CSV report {path:?}: {error}");
+    }
+
+    if args.report_unprocessed {
+        for record in records.iter().filter(|r| r.status == Status::Unprocessed) {
+            println!("Unprocessed ({}): {}", record.reason, record.source_path);
+        }
+    }
+
+    if args.emit_cfg_snippet {
+        print_cfg_snippet(&args.output_root());
+    }
+
+    if args.register {
+        register_output(&args.output_root(), &config);
+    }
+
+    let below_version = records
+        .iter()
+        .filter(|record| record.reason == "nif-version")
+        .count();
+    if below_version > 0 {
+        info!("Skipped {below_version} meshes below the minimum NIF version");
+    }
+
+    let existing = records
+        .iter()
+        .filter(|record| record.reason == "exists")
+        .count();
+    if existing > 0 {
+        info!("Skipped {existing} meshes that already exist in the output");
+    }
+
+    let unchanged = records
+        .iter()
+        .filter(|record| record.reason == "unchanged")
+        .count();
+    if unchanged > 0 {
+        info!("Skipped {unchanged} meshes that are already up to date");
+    }
+
+    let report = if args.strict {
+        ProcessReport::from_records_strict(&records, logger::warning_count())
+    } else {
+        ProcessReport::from_records(&records)
+    };
+
+    if args.summary_only {
+        println!("{}", report.summary_line());
+    } else {
+        info!("{}", report.summary_line());
+        info!("Output directory: {}", args.output_root().display());
+    }
+
+    if report.warnings > 0 {
+        eprintln!("Failing on {} warnings, see --strict", report.warnings);
+    }
+
+    if args.watch && !interrupted() {
+        watch(&args, &config, records);
+    }
+
+    if interrupted() {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+
+    if report.is_failure() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn output_keeps_the_authored_extension() {
+        let mesh_path = "w/w_arrow_odd.nif";
+        assert_eq!(
+            with_original_extension(mesh_path, r"W\W_Arrow_Odd.NIF"),
+            "w/w_arrow_odd.NIF"
+        );
+        assert_eq!(
+            with_original_extension(mesh_path, r"w\w_arrow_odd.nif"),
+            mesh_path
+        );
+    }
+
+    /// A projectile record as a plugin would define it.
+    fn weapon(id: &str, mesh: &str, weapon_type: WeaponType) -> Weapon {
+        Weapon {
+            id: id.into(),
+            mesh: mesh.into(),
+            data: WeaponData {
+                weapon_type,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Merge two plugins in load order, the second redefining `arrow`.
+    fn merge_conflict(policy: ConflictPolicy) -> (HashMap<String, Weapon>, WeaponCounts) {
+        let mut weapons = HashMap::new();
+        let mut origins = HashMap::new();
+        let base = [weapon("Arrow", r"w\base.nif", WeaponType::Arrow)];
+        let addon = [weapon("arrow", r"w\addon.nif", WeaponType::Arrow)];
+        merge_weapons("Base.esm", &base, &mut weapons, &mut origins, policy);
+        let counts = merge_weapons("Addon.esp", &addon, &mut weapons, &mut origins, policy);
+        (weapons, counts)
+    }
+
+    #[test]
+    fn addons_inherit_records_from_their_masters() {
+        let mut weapons = HashMap::new();
+        let mut origins = HashMap::new();
+        let policy = ConflictPolicy::LastWins;
+        let master = [
+            weapon("Arrow", r"w\arrow.nif", WeaponType::Arrow),
+            weapon("Bolt", r"w\bolt.nif", WeaponType::Bolt),
+        ];
+        // Restates the mesh and type, e.g. to change the speed.
+        let mut arrow = weapon("arrow", r"W\Arrow.nif", WeaponType::Arrow);
+        arrow.data.speed = 2.0;
+        let addon = [arrow];
+
+        merge_weapons("Morrowind.esm", &master, &mut weapons, &mut origins, policy);
+        let counts = merge_weapons("Addon.omwaddon", &addon, &mut weapons, &mut origins, policy);
+
+        assert_eq!(counts.conflicts, 0);
+        assert_eq!(weapons["arrow"].data.speed, 2.0);
+        assert_eq!(origins["arrow"], "Addon.omwaddon");
+        assert_eq!(weapons["bolt"].mesh, r"w\bolt.nif");
+        assert_eq!(origins["bolt"], "Morrowind.esm");
+    }
+
+    #[test]
+    fn conflicts_are_resolved_by_the_policy() {
+        let (weapons, counts) = merge_conflict(ConflictPolicy::LastWins);
+        assert_eq!(counts.conflicts, 1);
+        assert_eq!(weapons["arrow"].mesh, r"w\addon.nif");
+
+        let (weapons, counts) = merge_conflict(ConflictPolicy::FirstWins);
+        assert_eq!(counts.conflicts, 1);
+        assert_eq!(weapons["arrow"].mesh, r"w\base.nif");
+
+        // The run is failed by `load_content_weapons` once all are counted.
+        let (_, counts) = merge_conflict(ConflictPolicy::Error);
+        assert_eq!(counts.conflicts, 1);
+    }
+
+    #[test]
+    fn meshes_prefix_is_not_doubled() {
+        let mut weapon = weapon("Arrow", r"Meshes\W\W_Arrow.nif", WeaponType::Arrow);
+        assert_eq!(
+            format!("meshes/{}", mesh_key(&weapon)),
+            "meshes/w/w_arrow.nif"
+        );
+
+        weapon.mesh = r"W\W_Arrow.nif".into();
+        assert_eq!(
+            format!("meshes/{}", mesh_key(&weapon)),
+            "meshes/w/w_arrow.nif"
+        );
+
+        assert_eq!(strip_meshes_prefix("/meshes/w/a.nif"), "w/a.nif");
+        assert_eq!(strip_meshes_prefix("meshesx/a.nif"), "meshesx/a.nif");
+    }
+
+    #[test]
+    fn either_separator_gives_the_same_mesh() {
+        let back = weapon("Arrow", r"Meshes\W\a.nif", WeaponType::Arrow);
+        let forward = weapon("Arrow", "meshes/w/A.nif", WeaponType::Arrow);
+        assert_eq!(mesh_key(&back), mesh_key(&forward));
+
+        // The path written to, relative to the output directory.
+        let relative = |weapon: &Weapon| with_original_extension(&mesh_key(weapon), &weapon.mesh);
+        assert_eq!(relative(&back), relative(&forward));
+        assert_eq!(relative(&back), "w/a.nif");
+    }
+
+    #[test]
+    fn only_plugins_are_content_files() {
+        for name in ["Morrowind.esm", "Mod.ESP", "Addon.omwaddon", "Game.omwgame"] {
+            assert!(is_content_file(Path::new(name)), "{name}");
+        }
+        for name in ["Mod.omwscripts", "readme.txt", "esp"] {
+            assert!(!is_content_file(Path::new(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn shared_meshes_are_split_by_type() {
+        let weapons = HashMap::from([
+            (
+                "arrow".into(),
+                weapon("Arrow", r"w\shared.nif", WeaponType::Arrow),
+            ),
+            (
+                "bolt".into(),
+                weapon("Bolt", r"w\shared.nif", WeaponType::Bolt),
+            ),
+            (
+                "other".into(),
+                weapon("Other", r"w\shared.nif", WeaponType::Arrow),
+            ),
+        ]);
+
+        let gathered = gather_projectiles(&fixtures::args(&[]), &weapons);
+
+        let projectiles: Vec<_> = gathered
+            .projectiles
+            .iter()
+            .map(|&(_, weapon, projectile, _, split)| (weapon.id.as_str(), projectile, split))
+            .collect();
+        assert_eq!(
+            projectiles,
+            [
+                ("Arrow", Projectile::Arrow, true),
+                ("Bolt", Projectile::Bolt, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets_can_come_from_a_record_field() {
+        let mut arrow = weapon("Arrow", r"w\arrow.nif", WeaponType::Arrow);
+        arrow.data.reach = 1.5;
+        arrow.data.speed = 4.0;
+        let weapons = HashMap::from([("arrow".into(), arrow)]);
+
+        let offset = |args: &[&str]| {
+            let gathered = gather_projectiles(&fixtures::args(args), &weapons);
+            gathered.projectiles[0].3.offset
+        };
+
+        assert_eq!(offset(&["--offset-from-field=reach"]), 1.5);
+        assert_eq!(
+            offset(&["--offset-from-field=speed", "--offset-field-scale=-2"]),
+            -8.0
+        );
+    }
+
+    #[test]
+    fn record_hash_follows_only_the_changed_record() {
+        let arrow = weapon("Arrow", r"w\arrow.nif", WeaponType::Arrow);
+        let mut dart = weapon("Dart", r"w\dart.nif", WeaponType::Arrow);
+        let (arrow_hash, dart_hash) = (record_hash(&arrow), record_hash(&dart));
+
+        dart.data.weapon_type = WeaponType::MarksmanThrown;
+
+        assert_eq!(record_hash(&arrow), arrow_hash);
+        assert_ne!(record_hash(&dart), dart_hash);
+    }
+
+    /// Save meshes to the data directory of a test, and build a VFS over it.
+    fn data_vfs(dir: &Path, meshes: &[(&str, &NiStream)]) -> VFS {
+        let data = dir.join("data");
+        for (path, stream) in meshes {
+            save_nif(stream, &data.join(path)).unwrap();
+        }
+        VFS::from_directories(vec![data], None)
+    }
+
+    #[test]
+    fn only_changed_records_regenerate() {
+        let dir = fixtures::temp_dir("only-changed-records");
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Tri", Vec3::ONE);
+        fixtures::root(&mut stream, &[shape]);
+        let vfs = data_vfs(
+            &dir,
+            &[
+                ("meshes/w/arrow.nif", &stream),
+                ("meshes/w/dart.nif", &stream),
+            ],
+        );
+
+        // Made up front, so the output root resolves the same in every run.
+        std::fs::create_dir(dir.join("out")).unwrap();
+        let output_dir = format!("--output-dir={}", dir.join("out").display());
+        let manifest_path = dir.join("manifest.json");
+        let manifest = format!("--manifest={}", manifest_path.display());
+
+        let archives = Archives::open(&[], &[]);
+        let mut weapons = HashMap::from([
+            (
+                "arrow".into(),
+                weapon("Arrow", r"w\arrow.nif", WeaponType::Arrow),
+            ),
+            (
+                "dart".into(),
+                weapon("Dart", r"w\dart.nif", WeaponType::Arrow),
+            ),
+        ]);
+
+        // The ids of the weapons whose meshes a run wrote.
+        let run = |weapons: &HashMap<String, Weapon>, extra: &[&str]| {
+            let mut argv = vec![&*output_dir, &*manifest, "--only-changed-records"];
+            argv.extend(extra);
+            let args = fixtures::args(&argv);
+
+            let mut records = vec![];
+            let ignore = IgnoreRules::default();
+            process_weapons(&args, &vfs, &archives, weapons, &ignore, None, &mut records);
+            report::write_manifest(&manifest_path, &records).unwrap();
+
+            // Written outputs get a new time, so set it apart for the next run.
+            let stamp = std::time::SystemTime::UNIX_EPOCH;
+            let mut written = vec![];
+            for record in &records {
+                let file = std::fs::File::options()
+                    .write(true)
+                    .open(&record.output_path)
+                    .unwrap();
+                if file.metadata().unwrap().modified().unwrap() != stamp {
+                    written.push(record.weapon_id.clone());
+                }
+                file.set_modified(stamp).unwrap();
+            }
+            written.sort();
+            written
+        };
+
+        assert_eq!(run(&weapons, &[]), ["Arrow", "Dart"]);
+        assert!(run(&weapons, &[]).is_empty());
+
+        weapons.get_mut("dart").unwrap().data.weapon_type = WeaponType::Bolt;
+        assert_eq!(run(&weapons, &[]), ["Dart"]);
+
+        // Unchanged records are still regenerated when the arguments change.
+        assert_eq!(run(&weapons, &["--arrow-offset=5"]), ["Arrow"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn non_node_roots_can_be_skipped() {
+        let dir = fixtures::temp_dir("non-node-root");
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Tri", Vec3::ONE);
+        stream.roots.push(shape.cast());
+        let vfs = data_vfs(&dir, &[("meshes/w/arrow.nif", &stream)]);
+
+        let output_dir = format!("--output-dir={}", dir.join("out").display());
+        let args = fixtures::args(&[&output_dir, "--non-node-root=skip"]);
+        let weapons = HashMap::from([(
+            "arrow".into(),
+            weapon("Arrow", r"w\arrow.nif", WeaponType::Arrow),
+        )]);
+        let mut records = vec![];
+        let (archives, ignore) = (Archives::open(&[], &[]), IgnoreRules::default());
+        process_weapons(
+            &args,
+            &vfs,
+            &archives,
+            &weapons,
+            &ignore,
+            None,
+            &mut records,
+        );
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status, Status::Skipped);
+        assert_eq!(records[0].reason, "non-node-root");
+        assert!(!dir.join("out").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn summary_counts_follow_each_type() {
+        let dir = fixtures::temp_dir("summary-counts");
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Tri", Vec3::ONE);
+        fixtures::root(&mut stream, &[shape]);
+        let vfs = data_vfs(
+            &dir,
+            &[
+                ("meshes/w/arrow.nif", &stream),
+                ("meshes/w/bolt.nif", &stream),
+                ("meshes/w/ignored.nif", &stream),
+            ],
+        );
+
+        let output_dir = format!("--output-dir={}", dir.join("out").display());
+        let args = fixtures::args(&[&output_dir]);
+        let weapons = HashMap::from([
+            (
+                "arrow".into(),
+                weapon("Arrow", r"w\arrow.nif", WeaponType::Arrow),
+            ),
+            (
+                "bolt".into(),
+                weapon("Bolt", r"w\bolt.nif", WeaponType::Bolt),
+            ),
+            (
+                "ignored".into(),
+                weapon("Ignored", r"w\ignored.nif", WeaponType::Bolt),
+            ),
+            (
+                "dart".into(),
+                weapon("Dart", r"w\missing.nif", WeaponType::MarksmanThrown),
+            ),
+        ]);
+        let ignore = IgnoreRules {
+            rules: vec![crate::ignore::Rule {
+                pattern: "ignored.nif".into(),
+                source: PathBuf::from(crate::ignore::IGNORE_FILE),
+                line: 1,
+            }],
+        };
+        let mut records = vec![];
+        let archives = Archives::open(&[], &[]);
+        process_weapons(
+            &args,
+            &vfs,
+            &archives,
+            &weapons,
+            &ignore,
+            None,
+            &mut records,
+        );
+
+        // Records come out in mesh path order, whatever the map order.
+        let ids: Vec<_> = records.iter().map(|r| r.weapon_id.as_str()).collect();
+        assert_eq!(ids, ["Arrow", "Bolt", "Ignored", "Dart"]);
+
+        let counts = |weapon_type: &str| {
+            let records: Vec<_> = records
+                .iter()
+                .filter(|record| record.weapon_type == weapon_type)
+                .cloned()
+                .collect();
+            let report = ProcessReport::from_records(&records);
+            (report.processed, report.skipped, report.failed)
+        };
+        assert_eq!(counts("Arrow"), (1, 0, 0));
+        assert_eq!(counts("Bolt"), (1, 1, 0));
+        assert_eq!(counts("MarksmanThrown"), (0, 0, 1));
+        assert_eq!(
+            ProcessReport::from_records(&records).summary_line(),
+            "processed=2 skipped=1 failed=1"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::cli::{Params, Projectile};
+
+/// Transform values for a single mesh or weapon, see `--overrides`.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Override {
+    pub offset: Option<f32>,
+    pub scale: Option<f32>,
+    /// Leave the mesh or weapon out entirely.
+    pub skip: bool,
+}
+
+impl Override {
+    /// Replace the values given by the command line arguments.
+    ///
+    /// The scale of throwables is what flips them, so it's left as is.
+    ///
+    pub fn apply(&self, params: &mut Params) {
+        if let Some(offset) = self.offset {
+            params.offset = offset;
+        }
+        if let Some(scale) = self.scale
+            && params.projectile != Projectile::Thrown
+        {
+            params.scale = scale;
+        }
+    }
+}
+
+/// Overrides keyed by mesh path and by weapon id.
+///
+/// Keys are matched case insensitively. Mesh paths are relative to the
+/// meshes directory, with either separator. A weapon override takes
+/// precedence over one for its mesh.
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    pub meshes: HashMap<String, Override>,
+    pub weapons: HashMap<String, Override>,
+}
+
+impl Overrides {
+    /// The override for a weapon, given its id and normalized mesh path.
+    pub fn get(&self, weapon_id: &str, mesh_key: &str) -> Option<&Override> {
+        self.weapons
+            .get(&weapon_id.to_lowercase())
+            .or_else(|| self.meshes.get(mesh_key))
+    }
+}
+
+/// Parse an overrides file of the form:
+///
+/// ```toml
+/// [meshes."w/w_arrow_odd.nif"]
+/// offset = 4.0
+/// scale = 0.9
+///
+/// [weapons.my_broken_bolt]
+/// skip = true
+/// ```
+///
+pub fn parse_overrides(s: &str) -> Result<Overrides, String> {
+    let text = std::fs::read_to_string(Path::new(s)).map_err(|e| format!("{s:?}: {e}"))?;
+    let overrides: Overrides = toml::from_str(&text).map_err(|e| format!("{s:?}: {e}"))?;
+
+    // Normalize the keys the same way as the records, see `mesh_key`.
+    let mut normalized = Overrides::default();
+    for (path, value) in overrides.meshes {
+        let key = crate::strip_meshes_prefix(&path.to_lowercase()).replace('\\', "/");
+        if normalized.meshes.insert(key, value).is_some() {
+            return Err(format!("{s:?}: mesh {path:?} is listed more than once"));
+        }
+    }
+    for (id, value) in overrides.weapons {
+        if normalized
+            .weapons
+            .insert(id.to_lowercase(), value)
+            .is_some()
+        {
+            return Err(format!("{s:?}: weapon {id:?} is listed more than once"));
+        }
+    }
+
+    Ok(normalized)
+}
@@ -0,0 +1,1647 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use clap::ValueEnum;
+use log::{debug, warn};
+
+use tes3::nif::*;
+
+use crate::cli::{
+    Args, NcMethod, NcPosition, Params, Projectile, ScalePivot, WrapMode, parse_vec3,
+};
+use crate::error::PincushionError;
+use crate::geometry;
+
+/// Offset an object along the axis of its projectile type, and scale it.
+///
+/// Arrows and bolts are usually shrunk, throwables are flipped (-1 scale).
+fn process(object: &mut NiAVObject, params: &Params) {
+    object.translation += params.offset_vec();
+    object.scale *= params.scale;
+}
+
+/// Round the transform of an object to the given number of decimals.
+fn round_transform(object: &mut NiAVObject, decimals: u8) {
+    let factor = 10f32.powi(decimals.into());
+    let round = |value: f32| (value * factor).round() / factor;
+
+    object.translation = object.translation.map(round);
+    object.rotation = Mat3::from_cols(
+        object.rotation.x_axis.map(round),
+        object.rotation.y_axis.map(round),
+        object.rotation.z_axis.map(round),
+    );
+    object.scale = round(object.scale);
+}
+
+/// Rotate an object about its own origin, in its local space.
+///
+/// The new rotation is composed on the right, `R' = R * rotation`, so it acts
+/// on the geometry before the existing rotation does. A pre-rotated node thus
+/// turns about its own axes, not the parent's. The scale stays a scalar, and
+/// as a uniform scale commutes with any rotation no shear can be introduced,
+/// whatever the order of the scale and rotation arguments.
+///
+fn rotate_local(object: &mut NiAVObject, rotation: Mat3) {
+    object.rotation *= rotation;
+}
+
+/// Whether an object is mirrored, judged by its geometry if it has any and
+/// by whether its transform flips handedness otherwise.
+fn is_mirrored(stream: &NiStream, link: NiLink<NiAVObject>) -> bool {
+    geometry::is_mirrored(stream, link).unwrap_or_else(|| {
+        stream
+            .get(link)
+            .is_some_and(|object| object.rotation.determinant() * object.scale < 0.0)
+    })
+}
+
+/// Scales outside of this range produce bounds that break culling.
+const SANE_SCALE: std::ops::RangeInclusive<f32> = 1e-3..=1e3;
+
+/// Flags assigned to inserted root nodes. (Visible, not app-culled)
+pub const DEFAULT_ROOT_FLAGS: u16 = 0x0000;
+
+/// Name given to inserted root nodes.
+///
+/// Reprocessing one of our own meshes recognizes the wrapper by this name and
+/// our marker, and transforms the original root beneath it rather than
+/// wrapping it again, whatever the wrap mode.
+///
+const WRAPPER_NAME: &str = "PincushionRoot";
+
+/// Whether the root is a wrapper inserted by an earlier run.
+fn has_own_wrapper(stream: &NiStream) -> bool {
+    let root = stream
+        .roots
+        .first()
+        .and_then(|root| stream.objects.get(root.key));
+    has_marker_tag(stream)
+        && matches!(root, Some(NiType::NiNode(node)) if node.name == WRAPPER_NAME)
+}
+
+/// Insert a new parent node above the previous root node.
+///
+/// The engine ignores transformations on root nodes, so we must
+/// do this before we can transform the original file root node.
+///
+fn insert_root_parent(stream: &mut NiStream, flags: u16) -> &mut NiNode {
+    let mut node = NiNode::default();
+
+    // Don't rely on the default flags, a hidden root hides everything.
+    node.flags = flags;
+    node.name = WRAPPER_NAME.into();
+
+    // Make all previous roots children of the new node. Malformed files may
+    // list the same root more than once, it must only become a child once.
+    let mut seen = HashSet::new();
+    for root in &stream.roots {
+        if seen.insert(root.key) {
+            node.children.push(root.cast());
+        }
+    }
+
+    // Insert the new node and assign it as the scene root.
+    let link = stream.insert(node);
+    stream.roots.clear();
+    stream.roots.push(link.cast());
+
+    stream.get_mut(link).unwrap()
+}
+
+/// The shift that moves the descendant named `anchor` to the given offset
+/// from the origin, e.g. to make an arrow tip meet the surface.
+///
+/// The first target holding such a descendant decides the shift, so that all
+/// targets can be moved together. Returns None if no target holds one.
+///
+fn anchor_shift(
+    stream: &NiStream,
+    targets: &[NiLink<NiAVObject>],
+    anchor: &str,
+    offset: Vec3,
+) -> Option<Vec3> {
+    targets.iter().find_map(|&link| {
+        let anchor_link = geometry::find_named(stream, link, anchor)?;
+        let transform = geometry::transform_to(stream, link, anchor_link)?;
+        // The anchor's position in the parent space, with our transforms applied.
+        Some(offset - transform.translation)
+    })
+}
+
+/// Node flags that enable collision generation.
+const COLLISION_FLAGS: u16 = 0x0002 | 0x0004;
+
+/// Clear the collision flags of the root and of the transformed targets.
+///
+/// A wrapper inserted by us has none set, the original root is one of the
+/// targets beneath it then.
+///
+fn clear_collision_flags(stream: &mut NiStream, targets: &[NiLink<NiAVObject>], key: &str) {
+    match stream.get_as_mut::<_, NiAVObject>(stream.roots[0]) {
+        Some(root) => root.flags &= !COLLISION_FLAGS,
+        None => warn!("Root is not a NiAVObject, can't clear its collision flags: {key}"),
+    }
+
+    for &link in targets {
+        if let Some(object) = stream.get_mut(link) {
+            object.flags &= !COLLISION_FLAGS;
+        }
+    }
+}
+
+/// Prefix of the string extra data that marks meshes as generated by us.
+pub const MARKER: &str = "PincushionGenerator";
+
+/// The root node's extra data, in order.
+///
+/// Malformed files may link the chain back onto itself, Each entry is only
+/// listed once, so relinking the result breaks the cycle without losing any
+/// entries.
+///
+fn extra_data_chain(stream: &NiStream) -> Vec<NiLink<NiExtraData>> {
+    let Some(root) = stream.get_as::<_, NiObjectNET>(stream.roots[0]) else {
+        return vec![];
+    };
+
+    let mut chain = vec![];
+    let mut visited = HashSet::new();
+    let mut next = root.extra_data;
+    while let Some(extra_data) = stream.get(next) {
+        if !visited.insert(next.key) {
+            warn!("Extra data chain of the root node is cyclic, relinking it");
+            break;
+        }
+        chain.push(next);
+        next = extra_data.next;
+    }
+
+    chain
+}
+
+/// Link the root node's extra data into a chain in the given order.
+fn relink_extra_data(stream: &mut NiStream, chain: &[NiLink<NiExtraData>]) {
+    let mut next = NiLink::default();
+    for &link in chain.iter().rev() {
+        stream.get_mut(link).unwrap().next = next;
+        next = link;
+    }
+
+    if let Some(root) = stream.get_as_mut::<_, NiObjectNET>(stream.roots[0]) {
+        root.extra_data = next;
+    }
+}
+
+/// Insert a string extra data at the front of the root node's extra data.
+fn insert_string_tag(stream: &mut NiStream, value: &str) {
+    let mut extra_data = NiStringExtraData::default();
+    extra_data.value = value.into();
+
+    let mut chain = extra_data_chain(stream);
+    chain.insert(0, stream.insert(extra_data).cast());
+    relink_extra_data(stream, &chain);
+}
+
+/// Append a string extra data to the end of the root node's extra data.
+fn append_string_tag(stream: &mut NiStream, value: &str) {
+    let mut extra_data = NiStringExtraData::default();
+    extra_data.value = value.into();
+
+    let mut chain = extra_data_chain(stream);
+    chain.push(stream.insert(extra_data).cast());
+    relink_extra_data(stream, &chain);
+}
+
+fn insert_no_collision_tag(stream: &mut NiStream, position: NcPosition) {
+    match position {
+        NcPosition::Front => insert_string_tag(stream, "NC"),
+        NcPosition::Back => append_string_tag(stream, "NC"),
+    }
+}
+
+/// Remove any string extra data from the root node that matches `predicate`.
+fn remove_string_tags(stream: &mut NiStream, predicate: impl Fn(&str) -> bool) {
+    let (removed, kept): (Vec<_>, Vec<_>) =
+        extra_data_chain(stream).into_iter().partition(|&link| {
+            stream
+                .get_as::<_, NiStringExtraData>(link)
+                .is_some_and(|extra_data| predicate(&extra_data.value))
+        });
+
+    // Relink the remaining entries, preserving their order.
+    relink_extra_data(stream, &kept);
+
+    for link in removed {
+        stream.objects.remove(link.key);
+    }
+}
+
+/// The transform recorded in our marker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    pub offset: Vec3,
+    pub scale: f32,
+    /// The point that was scaled about, if not the origin of each target.
+    pub pivot: Option<Vec3>,
+    /// The scale that was applied about the pivot, if it differs from `scale`
+    /// because it has been baked into the geometry since.
+    pub pivot_scale: Option<f32>,
+    /// The scale baked into the geometry rather than kept on the nodes. This
+    /// includes the sign of a flipped throwable.
+    pub baked: Vec3,
+    /// The `content_hash` of the source and parameters, if recorded.
+    pub hash: Option<u64>,
+    /// The rotation composed in local space, e.g. from jitter.
+    pub rotation: Option<Mat3>,
+    /// The projectile type the transform was made for. Not recorded by older
+    /// versions.
+    pub projectile: Option<Projectile>,
+    /// The names of the transformed nodes, in order. Not recorded by older
+    /// versions.
+    pub targets: Option<Vec<String>>,
+}
+
+impl Marker {
+    /// Undo the recorded transform of a target.
+    ///
+    /// Scaling by `s` about a pivot `q` and then offsetting by `o` moves the
+    /// translation `t` to `q + s * (t - q) + o`, so the original translation
+    /// is `q + (t - o - q) / s`. Without a pivot this is just `t - o`.
+    ///
+    fn undo(&self, object: &mut NiAVObject) {
+        let moved = object.translation - self.offset;
+        let pivot_scale = self.pivot_scale.unwrap_or(self.scale);
+        object.translation = match self.pivot {
+            Some(pivot) => pivot + (moved - pivot) / pivot_scale,
+            None => moved,
+        };
+        object.scale /= self.scale;
+        if let Some(rotation) = self.rotation {
+            rotate_local(object, rotation.transpose());
+        }
+    }
+
+    /// Undo the recorded bake of a target's geometry.
+    fn unbake(&self, stream: &mut NiStream, link: NiLink<NiAVObject>) {
+        if self.baked != Vec3::ONE {
+            geometry::bake_scale(stream, link, self.baked.recip());
+        }
+    }
+}
+
+/// Tag the mesh so that later runs can recognize it as our own output.
+///
+/// The applied transform is recorded so that it can be undone when the mesh
+/// is processed again.
+///
+fn insert_marker_tag(stream: &mut NiStream, marker: &Marker) {
+    let Vec3 { x, y, z } = marker.offset;
+    let mut value = format!("{MARKER} offset={x},{y},{z} scale={}", marker.scale);
+    if let Some(Vec3 { x, y, z }) = marker.pivot {
+        value += &format!(" pivot={x},{y},{z}");
+    }
+    if let Some(pivot_scale) = marker.pivot_scale {
+        value += &format!(" pivot_scale={pivot_scale}");
+    }
+    if marker.baked != Vec3::ONE {
+        let Vec3 { x, y, z } = marker.baked;
+        value += &format!(" baked={x},{y},{z}");
+    }
+    if let Some(rotation) = marker.rotation {
+        let values = rotation.to_cols_array().map(|v| v.to_string()).join(",");
+        value += &format!(" rotation={values}");
+    }
+    if let Some(projectile) = marker.projectile {
+        value += &format!(
+            " type={}",
+            projectile.to_possible_value().unwrap().get_name()
+        );
+    }
+    if let Some(targets) = &marker.targets {
+        // Every name is terminated, so an unnamed target isn't lost.
+        let names: String = targets.iter().map(|name| escape_name(name) + ",").collect();
+        value += &format!(" targets={names}");
+    }
+    if let Some(hash) = marker.hash {
+        value += &format!(" hash={hash:016x}");
+    }
+    insert_string_tag(stream, &value);
+}
+
+/// Percent-encode the characters of a node name that would end a marker field
+/// or the name itself.
+fn escape_name(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c == '%' || c == ',' || c.is_whitespace() {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                escaped += &format!("%{byte:02X}");
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// The inverse of `escape_name`.
+fn unescape_name(escaped: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = escaped.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Find the nodes listed in a marker's `targets` below the root.
+///
+/// Nodes sharing a name are matched in the order they're found walking down
+/// from the root, which is the order the earlier run listed them in.
+///
+fn find_targets(stream: &NiStream, names: &[String]) -> Vec<NiLink<NiAVObject>> {
+    let mut nodes = vec![];
+    let mut visited = HashSet::new();
+    let mut pending = match stream.get_as::<_, NiNode>(stream.roots[0]) {
+        Some(root) => root.children.iter().rev().copied().collect(),
+        None => vec![],
+    };
+    while let Some(link) = pending.pop() {
+        if !visited.insert(link.key) {
+            continue;
+        }
+        let Some(object) = stream.get(link) else {
+            continue;
+        };
+        nodes.push((link, object.name.to_string()));
+        if let Some(node) = stream.get_as::<_, NiNode>(link) {
+            pending.extend(node.children.iter().rev());
+        }
+    }
+
+    let mut targets = vec![];
+    for name in names {
+        let found = nodes.iter().position(|(link, node_name)| {
+            node_name == name
+                && !targets
+                    .iter()
+                    .any(|target: &NiLink<_>| target.key == link.key)
+        });
+        if let Some(index) = found {
+            targets.push(nodes[index].0);
+        }
+    }
+    targets
+}
+
+/// Record a `content_hash` in the marker of a processed mesh.
+pub fn insert_marker_hash(stream: &mut NiStream, hash: u64) {
+    for link in extra_data_chain(stream) {
+        if let Some(extra_data) = stream.get_as_mut::<_, NiStringExtraData>(link)
+            && extra_data.value.starts_with(MARKER)
+        {
+            extra_data.value += &format!(" hash={hash:016x}");
+            return;
+        }
+    }
+}
+
+/// A hash of everything that determines the output for a source mesh.
+///
+/// This covers the source bytes, the tool version, the parameters and every
+/// argument read by `apply_pincushion`. An unchanged hash means regenerating
+/// the mesh would give the same result.
+///
+pub fn content_hash(source: &[u8], args: &Args, params: &Params) -> u64 {
+    let settings = format!(
+        "{params:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} \
+         {:?}",
+        args.anchor_node,
+        args.auto,
+        args.auto_length,
+        args.auto_flip,
+        args.bake_skinned_scale,
+        args.geometry_only,
+        args.nc_method,
+        args.nc_position,
+        args.no_nc_for,
+        args.optimize,
+        args.root_flags,
+        args.root_name,
+        args.round_transforms,
+        args.scale_pivot,
+        args.seed,
+        args.strip_lod,
+        args.transform_nodes,
+    );
+    let hash = geometry::fnv1a(geometry::FNV_OFFSET, env!("CARGO_PKG_VERSION").as_bytes());
+    let hash = geometry::fnv1a(hash, settings.as_bytes());
+    geometry::fnv1a(hash, source)
+}
+
+/// The transform recorded in our marker, if the mesh has one.
+pub fn read_marker_tag(stream: &NiStream) -> Option<Marker> {
+    let value = stream
+        .objects_of_type::<NiStringExtraData>()
+        .find_map(|extra_data| extra_data.value.strip_prefix(MARKER))?;
+
+    let mut marker = Marker {
+        offset: Vec3::ZERO,
+        scale: 1.0,
+        pivot: None,
+        pivot_scale: None,
+        baked: Vec3::ONE,
+        hash: None,
+        rotation: None,
+        projectile: None,
+        targets: None,
+    };
+
+    for field in value.split_whitespace() {
+        match field.split_once('=') {
+            Some(("offset", v)) => marker.offset = parse_vec3(v).ok()?,
+            Some(("scale", v)) => marker.scale = v.parse().ok()?,
+            Some(("pivot", v)) => marker.pivot = Some(parse_vec3(v).ok()?),
+            Some(("pivot_scale", v)) => marker.pivot_scale = Some(v.parse().ok()?),
+            Some(("baked", v)) => marker.baked = parse_vec3(v).ok()?,
+            Some(("hash", v)) => marker.hash = Some(u64::from_str_radix(v, 16).ok()?),
+            Some(("type", v)) => marker.projectile = Some(Projectile::from_str(v, true).ok()?),
+            Some(("targets", v)) => {
+                let names = v.split_terminator(',').map(unescape_name);
+                marker.targets = Some(names.collect::<Option<_>>()?);
+            }
+            Some(("rotation", v)) => {
+                let values: Vec<f32> = v
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .ok()?;
+                marker.rotation = Some(Mat3::from_cols_slice(values.get(..9)?));
+            }
+            _ => {}
+        }
+    }
+
+    Some(marker)
+}
+
+/// Whether the mesh has collision disabled through an "NC" string extra data.
+pub fn has_no_collision_tag(stream: &NiStream) -> bool {
+    stream
+        .objects_of_type::<NiStringExtraData>()
+        .any(|extra_data| extra_data.value.starts_with("NC"))
+}
+
+pub fn has_marker_tag(stream: &NiStream) -> bool {
+    stream
+        .objects_of_type::<NiStringExtraData>()
+        .any(|extra_data| extra_data.value.starts_with(MARKER))
+}
+
+/// Load a NIF file, memory mapping it where possible.
+///
+/// Anything that can't be mapped (empty files, special files) is read into a
+/// buffer instead.
+///
+pub fn load_nif(path: &Path) -> Result<NiStream, PincushionError> {
+    let error = |source| PincushionError::NifParse {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let file = std::fs::File::open(path).map_err(error)?;
+
+    let mut stream = NiStream::default();
+
+    // SAFETY: The map is read-only and dropped before we return. If the file
+    // is modified while mapped the worst outcome is a failed parse.
+    match unsafe { memmap2::Mmap::map(&file) } {
+        Ok(mmap) => stream.load_bytes(&mmap).map_err(error)?,
+        Err(_) => stream
+            .load_bytes(&std::fs::read(path).map_err(error)?)
+            .map_err(error)?,
+    }
+
+    Ok(stream)
+}
+
+/// Check that a mesh has exactly one root, as `apply_pincushion` expects.
+pub fn check_root_count(stream: &NiStream, path: &Path) -> Result<(), PincushionError> {
+    match stream.roots.len() {
+        1 => Ok(()),
+        count => Err(PincushionError::InvalidRootCount {
+            path: path.to_path_buf(),
+            count,
+        }),
+    }
+}
+
+/// Header prefixes of the NIF versions we could encounter.
+const NIF_MAGIC: [&[u8]; 2] = [b"NetImmerse File Format", b"Gamebryo File Format"];
+
+/// Check whether a file starts with a NIF header.
+///
+/// Used to tell files that merely failed to parse apart from other files that
+/// shadow a mesh path, e.g. a texture or archive misplaced by a mod.
+///
+pub fn has_nif_header(path: &Path) -> bool {
+    let mut header = [0; 22];
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let count = std::io::Read::read(&mut file, &mut header).unwrap_or(0);
+    NIF_MAGIC
+        .iter()
+        .any(|magic| header[..count].starts_with(magic))
+}
+
+/// Read the version from the header line of a NIF file, e.g. "Version 4.0.0.2".
+pub fn read_nif_version(path: &Path) -> Option<u32> {
+    let mut header = [0; 64];
+    let mut file = std::fs::File::open(path).ok()?;
+    let count = std::io::Read::read(&mut file, &mut header).ok()?;
+
+    let line = header[..count].split(|&b| b == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+    let (_, version) = line.rsplit_once("Version ")?;
+
+    parse_nif_version(version.trim()).ok()
+}
+
+/// Parse a dotted NIF version into its packed form, e.g. 4.0.0.2 to 0x04000002.
+pub fn parse_nif_version(s: &str) -> Result<u32, String> {
+    let parts: Vec<u8> = s
+        .split('.')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("invalid version {s:?}: {e}"))?;
+
+    match parts[..] {
+        [a, b, c, d] => Ok(u32::from_be_bytes([a, b, c, d])),
+        _ => Err(format!("expected a version like 4.0.0.2, found {s:?}")),
+    }
+}
+
+/// Whether `apply_pincushion` will wrap the root in a new node. By default
+/// only a plain NiNode root is used as is, and our own wrapper always is.
+pub fn needs_root_wrapper(stream: &NiStream, wrap: WrapMode) -> bool {
+    if has_own_wrapper(stream) {
+        return false;
+    }
+    match wrap {
+        WrapMode::Auto => !matches!(
+            stream.objects.get(stream.roots[0].key),
+            Some(NiType::NiNode(_))
+        ),
+        WrapMode::Always => true,
+        WrapMode::Never => false,
+    }
+}
+
+/// The lengths that `--auto` scales to without `--auto-length`.
+const AUTO_ARROW_LENGTH: f32 = 60.0;
+const AUTO_BOLT_LENGTH: f32 = 40.0;
+
+/// Resolve the relative offset and scale of `--auto` for a mesh.
+///
+/// The length is measured along the projectile axis, over the geometry of
+/// all targets in their parent's space, i.e. before any of our transforms.
+/// Meshes without measurable geometry keep the values as given.
+///
+fn auto_size(
+    stream: &NiStream,
+    targets: &[NiLink<NiAVObject>],
+    args: &Args,
+    params: &mut Params,
+    key: &str,
+) {
+    let axis = params.axis.unit();
+    let length =
+        geometry::bounding_box(stream, targets).map(|(min, max)| (max - min).dot(axis).abs());
+    let Some(length) = length.filter(|&length| length > f32::EPSILON) else {
+        warn!("No geometry to measure for --auto, using the values as given: {key}");
+        return;
+    };
+    let target = match params.projectile {
+        Projectile::Arrow => Some(args.auto_length.unwrap_or(AUTO_ARROW_LENGTH)),
+        Projectile::Bolt => Some(args.auto_length.unwrap_or(AUTO_BOLT_LENGTH)),
+        Projectile::Thrown => None,
+    };
+    if let Some(target) = target {
+        params.scale *= target / length;
+    }
+    params.offset *= length * params.scale.abs();
+    debug!(
+        "Measured length {length}, using offset={} scale={}: {key}",
+        params.offset, params.scale
+    );
+}
+
+/// The name of the transformed node in meshes from `template_stream`.
+const TEMPLATE_NAME: &str = "PincushionTemplate";
+
+/// A mesh holding nothing but the transform for one projectile type.
+///
+/// The root node has a single empty child, which is transformed exactly like
+/// the child of a real mesh. Its transform and marker can then be read back
+/// with `extract-transform`, see `--save-template`.
+///
+/// Returns the reason too if nothing could be transformed, e.g. because the
+/// child was filtered out by `--geometry-only`.
+///
+pub fn template_stream(args: &Args, params: &Params) -> (NiStream, Option<&'static str>) {
+    let mut stream = NiStream::default();
+
+    let mut child = NiNode::default();
+    child.name = TEMPLATE_NAME.into();
+    let child = stream.insert(child);
+
+    let mut root = NiNode::default();
+    root.flags = args.root_flags;
+    root.children.push(child.cast());
+    let root = stream.insert(root);
+    stream.roots.push(root.cast());
+
+    let reason = apply_pincushion(&mut stream, args, params, TEMPLATE_NAME);
+    (stream, reason)
+}
+
+/// Apply the pincushion transforms to a mesh with exactly one root.
+///
+/// Meshes we generated previously have their old transform undone first,
+/// otherwise each run would stack on top of the last. The `key` identifies
+/// the mesh in logs and, with the projectile type, seeds its jitter.
+///
+/// Returns the reason if nothing meaningful could be transformed.
+///
+pub fn apply_pincushion(
+    stream: &mut NiStream,
+    args: &Args,
+    params: &Params,
+    key: &str,
+) -> Option<&'static str> {
+    // Wrapping nothing would produce an empty, tagged file. Leave it alone.
+    if stream.roots.is_empty() {
+        warn!("Mesh has no root nodes, leaving it untouched: {key}");
+        return Some("no-roots");
+    }
+
+    let previous = read_marker_tag(stream);
+    let own_wrapper = has_own_wrapper(stream);
+
+    if previous.is_some() {
+        remove_string_tags(stream, |value| value == "NC" || value.starts_with(MARKER));
+    }
+
+    // Must match what `needs_root_wrapper` reports.
+    let mut targets = match params.wrap {
+        // Wrapped by an earlier run already.
+        _ if own_wrapper => {
+            let root = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+            root.children.clone()
+        }
+        WrapMode::Auto => match stream.objects.get(stream.roots[0].key) {
+            Some(NiType::NiNode(node)) => node.children.clone(),
+            // Transforming the switched children would move every state, active
+            // or not. Wrap instead so the switch and its index are left as is.
+            Some(NiType::NiSwitchNode(_)) => {
+                warn!("Wrapping switch node root instead of transforming its states: {key}");
+                insert_root_parent(stream, args.root_flags).children.clone()
+            }
+            _ => insert_root_parent(stream, args.root_flags).children.clone(),
+        },
+        WrapMode::Always => insert_root_parent(stream, args.root_flags).children.clone(),
+        WrapMode::Never => match stream.get_as::<_, NiNode>(stream.roots[0]) {
+            Some(node) => node.children.clone(),
+            None => {
+                warn!("Root is not a node and wrapping is disabled, nothing to transform: {key}");
+                vec![]
+            }
+        },
+    };
+
+    // Collapse LOD nodes, the discarded levels are pruned once we're done.
+    let mut stripped = 0;
+    if args.strip_lod {
+        stripped = geometry::strip_lod(stream, stream.roots[0].cast());
+        if stripped > 0 {
+            debug!("Replaced {stripped} LOD nodes with their most detailed level: {key}");
+            let root = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+            targets = root.children.clone();
+        }
+    }
+
+    // Prefer a conventionally named node over the root's children.
+    if let Some(name) = &args.root_name
+        && let Some(link) = geometry::find_named(stream, stream.roots[0].cast(), name)
+        && link.key != stream.roots[0].key
+    {
+        debug!("Using node {name:?} as the transform target: {key}");
+        targets = vec![link];
+    }
+
+    // Leave lights, emitters and other helpers where they are.
+    let mut helpers = 0;
+    if args.geometry_only {
+        let count = targets.len();
+        targets.retain(|&link| geometry::has_geometry(stream, link));
+        helpers = count - targets.len();
+        if helpers > 0 {
+            debug!("Leaving {helpers} helper nodes in place: {key}");
+        }
+    }
+
+    // Leave everything but the listed nodes as authored.
+    let mut unlisted = 0;
+    if !args.transform_nodes.is_empty() {
+        let count = targets.len();
+        let is_listed = |name: &str| {
+            args.transform_nodes
+                .iter()
+                .any(|listed| listed.eq_ignore_ascii_case(name))
+        };
+        targets.retain(|&link| {
+            stream
+                .get(link)
+                .is_some_and(|object| is_listed(&object.name))
+        });
+        unlisted = count - targets.len();
+        for name in &args.transform_nodes {
+            let found = targets
+                .iter()
+                .filter_map(|&link| stream.get(link))
+                .any(|object| object.name.eq_ignore_ascii_case(name));
+            if !found {
+                warn!("Node {name:?} from --transform-nodes not found: {key}");
+            }
+        }
+    }
+
+    // Restore the original pose before anything looks at it. Baking again
+    // without undoing the previous bake would compound it, and in the case of
+    // a flipped throwable turn it back inside out. Only the nodes the earlier
+    // run transformed are restored, whatever this run selects.
+    if let Some(previous) = previous {
+        let undone = match &previous.targets {
+            Some(names) => {
+                let found = find_targets(stream, names);
+                if found.len() < names.len() {
+                    warn!(
+                        "Some previously transformed nodes are missing, not restoring them: {key}"
+                    );
+                }
+                found
+            }
+            None => targets.clone(),
+        };
+        for &child in &undone {
+            previous.unbake(stream, child);
+            if let Some(object) = stream.get_mut(child) {
+                previous.undo(object);
+            }
+        }
+    }
+
+    // Don't flip throwables that are mirrored already, that would turn them
+    // inside out again.
+    let mut params = *params;
+    if args.auto_flip
+        && params.projectile == Projectile::Thrown
+        && !targets.is_empty()
+        && targets.iter().all(|&link| is_mirrored(stream, link))
+    {
+        debug!("Already mirrored, not flipping: {key}");
+        params.scale = 1.0;
+    }
+    if args.auto {
+        auto_size(stream, &targets, args, &mut params, key);
+    }
+    let params = &params;
+
+    // The pivot is shared by all targets, so the marker can record it once.
+    let pivot = match args.scale_pivot {
+        ScalePivot::Origin => None,
+        ScalePivot::Center => {
+            geometry::bounding_box(stream, &targets).map(|(min, max)| (min + max) / 2.0)
+        }
+        ScalePivot::Tip => geometry::bounding_box(stream, &targets).map(|(min, max)| {
+            let center = (min + max) / 2.0;
+            let end = if params.offset < 0.0 { min } else { max };
+            let axis = params.axis.unit();
+            center + axis * (end - center).dot(axis)
+        }),
+    };
+
+    // Skinned geometry may not follow its node's scale, so optionally bake it.
+    let bake_scale = targets.iter().any(|&link| geometry::has_skin(stream, link));
+    let bake_scale = bake_scale && params.scale != 1.0;
+    if bake_scale && !args.bake_skinned_scale {
+        warn!("Skinned geometry may ignore the node scale, see --bake-skinned-scale: {key}");
+    }
+    let bake_scale = bake_scale && args.bake_skinned_scale;
+
+    // The jitter is baked once per mesh, every hit uses the same pose. A mesh
+    // shared by several types gets a different pose for each.
+    let jitter = params.jitter.map(|degrees| {
+        let key = format!("{key} {:?}", params.projectile);
+        geometry::Rng::new(args.seed, &key).rotation(degrees)
+    });
+
+    // Track meshes where nothing meaningful was transformed.
+    let target_count = targets.len();
+    let mut transformed = 0;
+    let mut collision_only = 0;
+
+    for &child in &targets {
+        if let Some(NiType::RootCollisionNode(_)) = stream.objects.get(child.key) {
+            collision_only += 1;
+        }
+        let Some(object) = stream.get_mut(child) else {
+            continue;
+        };
+        transformed += 1;
+        // Scaling about the pivot moves the translation by the same factor.
+        if let Some(pivot) = pivot {
+            object.translation = pivot + (object.translation - pivot) * params.scale;
+        }
+        process(object, params);
+        if let Some(jitter) = jitter {
+            rotate_local(object, jitter);
+        }
+        if !SANE_SCALE.contains(&object.scale.abs()) {
+            warn!(
+                "Extreme scale {} may produce broken bounds: {key}",
+                object.scale
+            );
+        }
+        if let Some(scale) = params.scale_vec {
+            let extent = scale.abs() * object.scale.abs();
+            if !SANE_SCALE.contains(&extent.min_element())
+                || !SANE_SCALE.contains(&extent.max_element())
+            {
+                warn!("Extreme per-axis scale {scale} may produce broken bounds: {key}");
+            }
+            geometry::bake_scale(stream, child, scale);
+        }
+        if bake_scale && let Some(object) = stream.get_mut(child) {
+            object.scale /= params.scale;
+            geometry::bake_scale(stream, child, Vec3::splat(params.scale));
+        }
+    }
+
+    // Move all targets together, the marker records the shift as part of the
+    // offset so that it's undone by --reprocess.
+    let mut offset = params.offset_vec();
+    if let Some(name) = &args.anchor_node
+        && params.projectile != Projectile::Thrown
+    {
+        match anchor_shift(stream, &targets, name, offset) {
+            Some(shift) => {
+                for &child in &targets {
+                    if let Some(object) = stream.get_mut(child) {
+                        object.translation += shift;
+                    }
+                }
+                offset += shift;
+            }
+            None => debug!("Anchor node {name:?} not found, using plain offset: {key}"),
+        }
+    }
+
+    for &child in &targets {
+        // Rounded last, once the full transform has been composed.
+        if let Some(decimals) = args.round_transforms
+            && let Some(object) = stream.get_mut(child)
+        {
+            round_transform(object, decimals);
+        }
+        // The final pose, once everything above has been applied.
+        if let Some(object) = stream.get(child) {
+            let (t, r) = (object.translation, object.rotation);
+            debug!(
+                "Transformed {:?}: translation=[{}, {}, {}] rotation=[{:?}, {:?}, {:?}] scale={}: {key}",
+                object.name, t.x, t.y, t.z, r.x_axis, r.y_axis, r.z_axis, object.scale
+            );
+        }
+    }
+
+    let unprocessed = if target_count == 0 && unlisted > 0 {
+        Some("unlisted-only")
+    } else if target_count == 0 && helpers > 0 {
+        Some("helpers-only")
+    } else if target_count == 0 {
+        Some("empty-root")
+    } else if transformed == 0 {
+        Some("unknown-type")
+    } else if transformed == collision_only {
+        Some("collision-only")
+    } else {
+        None
+    };
+
+    let apply_nc = !args.no_nc_for.contains(&params.projectile);
+
+    if apply_nc && matches!(args.nc_method, NcMethod::String | NcMethod::Both) {
+        insert_no_collision_tag(stream, args.nc_position);
+    }
+    if apply_nc && matches!(args.nc_method, NcMethod::Flag | NcMethod::Both) {
+        clear_collision_flags(stream, &targets, key);
+    }
+
+    let mut baked = params.scale_vec.unwrap_or(Vec3::ONE);
+    if bake_scale {
+        baked *= params.scale;
+    }
+
+    let marker = Marker {
+        offset,
+        // A baked scale is no longer on the nodes, it's undone by `baked`.
+        scale: if bake_scale { 1.0 } else { params.scale },
+        pivot,
+        // The translation still moved about the pivot by the full scale.
+        pivot_scale: (bake_scale && pivot.is_some()).then_some(params.scale),
+        baked,
+        hash: None,
+        rotation: jitter,
+        projectile: Some(params.projectile),
+        targets: Some(
+            targets
+                .iter()
+                .filter_map(|&link| stream.get(link))
+                .map(|object| object.name.to_string())
+                .collect(),
+        ),
+    };
+    insert_marker_tag(stream, &marker);
+
+    if args.optimize || stripped > 0 {
+        let count = geometry::prune_orphans(stream);
+        debug!("Removed {count} unreachable objects: {key}");
+    }
+
+    unprocessed
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::fixtures;
+
+    /// A mesh whose root has a single arrow node, holding a tetrahedron.
+    fn arrow_stream() -> (NiStream, NiLink<NiAVObject>) {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let arrow = fixtures::node(&mut stream, "Arrow", &[shape]);
+        fixtures::root(&mut stream, &[arrow]);
+        (stream, arrow)
+    }
+
+    /// Apply the transform that the arguments give for `projectile`.
+    fn apply(stream: &mut NiStream, args: &[&str], projectile: Projectile) -> Option<&'static str> {
+        let args = fixtures::args(args);
+        apply_pincushion(stream, &args, &args.params(projectile), "test")
+    }
+
+    /// Assert that `link` has the same transform and geometry in both streams.
+    fn assert_same_pose(expected: &NiStream, actual: &NiStream, link: NiLink<NiAVObject>) {
+        let (a, b) = (expected.get(link).unwrap(), actual.get(link).unwrap());
+        assert!(
+            a.translation.abs_diff_eq(b.translation, 1e-4),
+            "translation {} != {}",
+            a.translation,
+            b.translation
+        );
+        assert!(a.rotation.abs_diff_eq(b.rotation, 1e-4), "rotation differs");
+        assert!(
+            (a.scale - b.scale).abs() < 1e-4,
+            "scale {} != {}",
+            a.scale,
+            b.scale
+        );
+
+        let bounds = |stream: &NiStream| geometry::bounding_box(stream, &[link]).unwrap();
+        let ((a_min, a_max), (b_min, b_max)) = (bounds(expected), bounds(actual));
+        assert!(a_min.abs_diff_eq(b_min, 1e-4) && a_max.abs_diff_eq(b_max, 1e-4));
+    }
+
+    /// The number of string extra data whose value matches `predicate`.
+    fn count_tags(stream: &NiStream, predicate: impl Fn(&str) -> bool) -> usize {
+        stream
+            .objects_of_type::<NiStringExtraData>()
+            .filter(|extra_data| predicate(&extra_data.value))
+            .count()
+    }
+
+    /// The values of the root's string extra data, in chain order.
+    fn tag_values(stream: &NiStream) -> Vec<String> {
+        extra_data_chain(stream)
+            .into_iter()
+            .filter_map(|link| stream.get_as::<_, NiStringExtraData>(link))
+            .map(|extra_data| extra_data.value.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn reprocessing_is_deterministic() {
+        let (mut stream, arrow) = arrow_stream();
+        stream.get_mut(arrow).unwrap().translation = Vec3::new(1.0, 2.0, 3.0);
+        let args = [
+            "--arrow-offset=10",
+            "--arrow-scale=0.5",
+            "--arrow-scale-vec=1,2,1",
+            "--scale-pivot=center",
+            "--jitter-rotation=15",
+        ];
+
+        apply(&mut stream, &args, Projectile::Arrow);
+        let once = stream.clone();
+        apply(&mut stream, &args, Projectile::Arrow);
+
+        assert_same_pose(&once, &stream, arrow);
+        assert_eq!(count_tags(&stream, |value| value == "NC"), 1);
+        assert_eq!(count_tags(&stream, |value| value.starts_with(MARKER)), 1);
+    }
+
+    #[test]
+    fn scale_vec_is_baked_into_the_vertices() {
+        let (mut stream, arrow) = arrow_stream();
+
+        apply(
+            &mut stream,
+            &["--arrow-scale-vec=1,1,0.5"],
+            Projectile::Arrow,
+        );
+
+        let (min, max) = geometry::bounding_box(&stream, &[arrow]).unwrap();
+        assert!((max - min).abs_diff_eq(Vec3::new(1.0, 1.0, 0.5), 1e-6));
+        assert_eq!(stream.get(arrow).unwrap().scale, 1.0);
+    }
+
+    #[test]
+    fn duplicate_roots_become_a_single_child() {
+        let mut stream = NiStream::default();
+        let root = fixtures::root(&mut stream, &[]);
+        stream.roots.push(root.cast());
+
+        let wrapper = insert_root_parent(&mut stream, DEFAULT_ROOT_FLAGS);
+
+        assert_eq!(wrapper.children, [root]);
+        assert_eq!(stream.roots.len(), 1);
+    }
+
+    #[test]
+    fn anchor_shift_is_undone_by_reprocessing() {
+        let (mut stream, arrow) = arrow_stream();
+        let tip = fixtures::node(&mut stream, "ArrowTip", &[]);
+        stream
+            .get_as_mut::<_, NiNode>(arrow)
+            .unwrap()
+            .children
+            .push(tip);
+        stream.get_mut(tip).unwrap().translation = Vec3::new(0.0, 5.0, 0.0);
+        let args = [
+            "--arrow-offset=10",
+            "--arrow-scale=0.5",
+            "--anchor-node=ArrowTip",
+        ];
+
+        apply(&mut stream, &args, Projectile::Arrow);
+        let once = stream.clone();
+        apply(&mut stream, &args, Projectile::Arrow);
+
+        assert_same_pose(&once, &stream, arrow);
+        let offset = fixtures::args(&args).params(Projectile::Arrow).offset_vec();
+        let tip = geometry::transform_to(&stream, arrow, tip).unwrap();
+        assert!(tip.translation.abs_diff_eq(offset, 1e-4));
+    }
+
+    #[test]
+    fn offsets_follow_the_axis_of_each_type() {
+        let args = fixtures::args(&[
+            "--arrow-offset=10",
+            "--arrow-axis=z",
+            "--bolt-offset=5",
+            "--bolt-axis=x",
+        ]);
+
+        let offset = |projectile| {
+            let (mut stream, arrow) = arrow_stream();
+            apply_pincushion(&mut stream, &args, &args.params(projectile), "test");
+            stream.get(arrow).unwrap().translation
+        };
+
+        assert_eq!(offset(Projectile::Arrow), Vec3::new(0.0, 0.0, 10.0));
+        assert_eq!(offset(Projectile::Bolt), Vec3::new(5.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn switch_node_roots_are_wrapped() {
+        let mut stream = NiStream::default();
+        let on = fixtures::tetrahedron(&mut stream, "On", Vec3::ONE);
+        let off = fixtures::tetrahedron(&mut stream, "Off", Vec3::ONE);
+        let mut switch = NiSwitchNode::default();
+        switch.children = vec![on, off];
+        let switch = stream.insert(switch);
+        stream.roots.push(switch.cast());
+        let authored = stream.get(switch).unwrap().clone();
+
+        apply(&mut stream, &["--arrow-offset=10"], Projectile::Arrow);
+
+        let root = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+        assert_eq!(root.children, [switch.cast()]);
+
+        // Only the switch node itself moves, its states and index don't.
+        let mut switched = stream.get(switch).unwrap().clone();
+        assert_ne!(switched.translation, authored.translation);
+        switched.translation = authored.translation;
+        assert_eq!(switched, authored);
+        assert_eq!(stream.get(on).unwrap().translation, Vec3::ZERO);
+        assert_eq!(stream.get(off).unwrap().translation, Vec3::ZERO);
+    }
+
+    #[test]
+    fn auto_flip_leaves_mirrored_geometry_alone() {
+        let args = ["--auto-flip"];
+
+        let (mut stream, dart) = arrow_stream();
+        apply(&mut stream, &args, Projectile::Thrown);
+        assert_eq!(stream.get(dart).unwrap().scale, -1.0);
+
+        let (mut stream, dart) = arrow_stream();
+        geometry::bake_scale(&mut stream, dart, Vec3::NEG_ONE);
+        apply(&mut stream, &args, Projectile::Thrown);
+        assert_eq!(stream.get(dart).unwrap().scale, 1.0);
+    }
+
+    #[test]
+    fn no_collision_tag_follows_the_position() {
+        let (mut stream, _) = arrow_stream();
+        insert_string_tag(&mut stream, "MRK");
+
+        let mut front = stream.clone();
+        insert_no_collision_tag(&mut front, NcPosition::Front);
+        assert_eq!(tag_values(&front), ["NC", "MRK"]);
+
+        let mut back = stream.clone();
+        insert_no_collision_tag(&mut back, NcPosition::Back);
+        assert_eq!(tag_values(&back), ["MRK", "NC"]);
+    }
+
+    #[test]
+    fn geometry_only_leaves_lights_in_place() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let light = stream.insert(NiPointLight::default()).cast();
+        fixtures::root(&mut stream, &[shape, light]);
+
+        let reason = apply(
+            &mut stream,
+            &["--arrow-offset=10", "--geometry-only"],
+            Projectile::Arrow,
+        );
+
+        assert_eq!(reason, None);
+        assert_ne!(stream.get(shape).unwrap().translation, Vec3::ZERO);
+        assert_eq!(stream.get(light).unwrap().translation, Vec3::ZERO);
+    }
+
+    #[test]
+    fn reprocessing_restores_only_the_previous_targets() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let light = stream.insert(NiPointLight::default()).cast();
+        fixtures::root(&mut stream, &[shape, light]);
+
+        apply(
+            &mut stream,
+            &["--arrow-offset=10", "--geometry-only"],
+            Projectile::Arrow,
+        );
+        apply(&mut stream, &["--arrow-offset=10"], Projectile::Arrow);
+
+        // The light was never moved, so there was nothing to restore.
+        let offset = fixtures::args(&["--arrow-offset=10"])
+            .params(Projectile::Arrow)
+            .offset_vec();
+        assert_eq!(stream.get(shape).unwrap().translation, offset);
+        assert_eq!(stream.get(light).unwrap().translation, offset);
+    }
+
+    #[test]
+    fn marker_targets_keep_any_name() {
+        let names = ["Bip01 Arrow", "a,b", "100%", ""].map(String::from);
+        let mut stream = NiStream::default();
+        fixtures::root(&mut stream, &[]);
+        let marker = Marker {
+            offset: Vec3::ZERO,
+            scale: 1.0,
+            pivot: None,
+            pivot_scale: None,
+            baked: Vec3::ONE,
+            hash: None,
+            rotation: None,
+            projectile: None,
+            targets: Some(names.to_vec()),
+        };
+
+        insert_marker_tag(&mut stream, &marker);
+
+        assert_eq!(read_marker_tag(&stream), Some(marker));
+    }
+
+    #[test]
+    fn flag_method_clears_the_wrapped_root() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        let root = fixtures::root(&mut stream, &[shape]);
+        stream.get_mut(root).unwrap().flags = COLLISION_FLAGS;
+
+        apply(
+            &mut stream,
+            &["--arrow-wrap=always", "--nc-method=flag"],
+            Projectile::Arrow,
+        );
+
+        assert_ne!(stream.roots[0].key, root.key);
+        assert_eq!(stream.get(root).unwrap().flags & COLLISION_FLAGS, 0);
+    }
+
+    #[test]
+    fn center_pivot_keeps_the_center_in_place() {
+        let args = ["--arrow-scale=0.5", "--scale-pivot=center"];
+        let (mut origin, arrow) = arrow_stream();
+        let node = origin.get_mut(arrow).unwrap();
+        node.translation = Vec3::new(0.0, 10.0, 0.0);
+        node.scale = 2.0;
+        let mut center = origin.clone();
+        let before = geometry::bounding_box(&origin, &[arrow]).unwrap();
+
+        apply(&mut origin, &["--arrow-scale=0.5"], Projectile::Arrow);
+        apply(&mut center, &args, Projectile::Arrow);
+
+        // About the origin the node's own translation stays put.
+        assert_eq!(
+            origin.get(arrow).unwrap().translation,
+            Vec3::new(0.0, 10.0, 0.0)
+        );
+
+        // About the center the bounds shrink towards their middle, (1, 11, 1).
+        let moved = center.get(arrow).unwrap().translation;
+        assert!(moved.abs_diff_eq(Vec3::new(0.5, 10.5, 0.5), 1e-5));
+        let after = geometry::bounding_box(&center, &[arrow]).unwrap();
+        let middle = |(min, max): (Vec3, Vec3)| (min + max) / 2.0;
+        assert!(middle(before).abs_diff_eq(middle(after), 1e-5));
+    }
+
+    #[test]
+    fn baked_skinned_scale_is_undone_by_reprocessing() {
+        let (mut stream, arrow) = arrow_stream();
+        stream.get_mut(arrow).unwrap().translation = Vec3::new(0.0, 10.0, 0.0);
+        let skin = stream.insert(NiSkinInstance::default());
+        let shape = stream.get_as::<_, NiNode>(arrow).unwrap().children[0];
+        stream
+            .get_as_mut::<_, NiGeometry>(shape)
+            .unwrap()
+            .skin_instance = skin.cast();
+        let args = [
+            "--arrow-scale=0.5",
+            "--scale-pivot=center",
+            "--bake-skinned-scale",
+        ];
+
+        apply(&mut stream, &args, Projectile::Arrow);
+        let once = stream.clone();
+        apply(&mut stream, &args, Projectile::Arrow);
+
+        assert_same_pose(&once, &stream, arrow);
+        assert_eq!(stream.get(arrow).unwrap().scale, 1.0);
+        let (min, max) = geometry::bounding_box(&stream, &[arrow]).unwrap();
+        assert!((max - min).abs_diff_eq(Vec3::splat(0.5), 1e-5));
+    }
+
+    #[test]
+    fn thrown_as_arrow_is_offset_and_not_flipped() {
+        let (mut stream, dart) = arrow_stream();
+        let args = ["--thrown-as-arrow", "--arrow-offset=5", "--arrow-scale=0.5"];
+
+        apply(&mut stream, &args, Projectile::Thrown);
+
+        let dart = stream.get(dart).unwrap();
+        assert_eq!(dart.scale, 0.5);
+        assert_eq!(dart.translation, Vec3::new(0.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn strip_lod_keeps_only_the_detailed_level() {
+        let mut stream = NiStream::default();
+        let high = fixtures::tetrahedron(&mut stream, "High", Vec3::ONE);
+        let low = fixtures::tetrahedron(&mut stream, "Low", Vec3::ONE);
+        let mut lod = NiLODNode::default();
+        lod.children = vec![high, low];
+        lod.translation = Vec3::new(0.0, 0.0, 1.0);
+        let lod = stream.insert(lod).cast();
+        fixtures::root(&mut stream, &[lod]);
+
+        apply(&mut stream, &["--strip-lod"], Projectile::Arrow);
+
+        let root = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+        assert_eq!(root.children, [high]);
+        assert_eq!(
+            stream.get(high).unwrap().translation,
+            Vec3::new(0.0, 0.0, 1.0)
+        );
+        assert!(stream.get(lod).is_none());
+        assert!(stream.get(low).is_none());
+    }
+
+    #[test]
+    fn throwables_are_flipped_once() {
+        let (mut stream, dart) = arrow_stream();
+
+        apply(&mut stream, &[], Projectile::Thrown);
+        apply(&mut stream, &[], Projectile::Thrown);
+
+        assert_eq!(stream.get(dart).unwrap().scale, -1.0);
+        assert_eq!(count_tags(&stream, |value| value.starts_with(MARKER)), 1);
+    }
+
+    #[test]
+    fn streams_without_roots_are_left_alone() {
+        let mut stream = NiStream::default();
+
+        let reason = apply(&mut stream, &["--arrow-offset=10"], Projectile::Arrow);
+
+        assert_eq!(reason, Some("no-roots"));
+        assert!(stream.roots.is_empty());
+        assert!(stream.objects.is_empty());
+    }
+
+    #[test]
+    fn excluded_types_keep_their_collision() {
+        let args = ["--no-nc-for=thrown"];
+        let is_nc = |value: &str| value == "NC";
+
+        let (mut thrown, _) = arrow_stream();
+        apply(&mut thrown, &args, Projectile::Thrown);
+        assert_eq!(count_tags(&thrown, is_nc), 0);
+
+        for projectile in [Projectile::Arrow, Projectile::Bolt] {
+            let (mut stream, _) = arrow_stream();
+            apply(&mut stream, &args, projectile);
+            assert_eq!(count_tags(&stream, is_nc), 1, "{projectile:?}");
+        }
+    }
+
+    #[test]
+    fn no_collision_tag_keeps_the_whole_chain() {
+        let (mut stream, _) = arrow_stream();
+        for value in ["C", "B", "A"] {
+            insert_string_tag(&mut stream, value);
+        }
+
+        insert_no_collision_tag(&mut stream, NcPosition::Front);
+
+        assert_eq!(tag_values(&stream), ["NC", "A", "B", "C"]);
+    }
+
+    #[test]
+    fn no_collision_tag_breaks_cyclic_chains() {
+        let (mut stream, _) = arrow_stream();
+        for value in ["C", "B", "A"] {
+            insert_string_tag(&mut stream, value);
+        }
+        let chain = extra_data_chain(&stream);
+        stream.get_mut(chain[2]).unwrap().next = chain[0];
+
+        insert_no_collision_tag(&mut stream, NcPosition::Front);
+
+        assert_eq!(tag_values(&stream), ["NC", "A", "B", "C"]);
+        assert!(stream.get(stream.get(chain[2]).unwrap().next).is_none());
+    }
+
+    #[test]
+    fn non_node_roots_are_wrapped() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::ONE);
+        stream.roots.push(shape.cast());
+
+        let reason = apply(
+            &mut stream,
+            &["--arrow-offset=10", "--non-node-root=wrap"],
+            Projectile::Arrow,
+        );
+
+        assert_eq!(reason, None);
+        let root = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+        assert_eq!(root.children, [shape]);
+        assert_ne!(stream.get(shape).unwrap().translation, Vec3::ZERO);
+    }
+
+    #[test]
+    fn always_wraps_node_roots() {
+        let (mut stream, arrow) = arrow_stream();
+        let root = stream.roots[0];
+
+        apply(&mut stream, &["--arrow-wrap=always"], Projectile::Arrow);
+
+        let wrapper = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+        assert_eq!(wrapper.name, WRAPPER_NAME);
+        assert_eq!(wrapper.children, [root.cast()]);
+        // The original root is transformed, its children are left as they are.
+        let root = stream.get_as::<_, NiNode>(root).unwrap();
+        assert_eq!(root.children, [arrow]);
+    }
+
+    #[test]
+    fn wrappers_get_the_root_flags() {
+        let (mut stream, _) = arrow_stream();
+        apply(&mut stream, &["--arrow-wrap=always"], Projectile::Arrow);
+        let wrapper = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+        assert_eq!(wrapper.flags, DEFAULT_ROOT_FLAGS);
+
+        let (mut stream, _) = arrow_stream();
+        apply(
+            &mut stream,
+            &["--arrow-wrap=always", "--root-flags=0x0010"],
+            Projectile::Arrow,
+        );
+        let wrapper = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+        assert_eq!(wrapper.flags, 0x0010);
+    }
+
+    #[test]
+    fn reprocessing_reuses_our_wrapper() {
+        let (mut stream, _) = arrow_stream();
+        let root = stream.roots[0].cast();
+        let args = [
+            "--arrow-wrap=always",
+            "--arrow-offset=10",
+            "--arrow-scale=0.5",
+        ];
+
+        apply(&mut stream, &args, Projectile::Arrow);
+        let once = stream.clone();
+        assert!(!needs_root_wrapper(&stream, WrapMode::Always));
+        apply(&mut stream, &args, Projectile::Arrow);
+
+        assert_eq!(stream.roots, once.roots);
+        let wrapper = stream.get_as::<_, NiNode>(stream.roots[0]).unwrap();
+        assert_eq!(wrapper.children, [root]);
+        assert_same_pose(&once, &stream, root);
+    }
+
+    #[test]
+    fn content_hash_follows_the_arguments() {
+        let source = b"NetImmerse File Format";
+        let hash = |args: &[&str]| {
+            let args = fixtures::args(args);
+            content_hash(source, &args, &args.params(Projectile::Arrow))
+        };
+
+        let original = hash(&["--arrow-offset=10"]);
+        assert_eq!(original, hash(&["--arrow-offset=10"]));
+        assert_ne!(original, hash(&["--arrow-offset=11"]));
+        assert_ne!(
+            original,
+            hash(&["--arrow-offset=10", "--scale-pivot=center"])
+        );
+
+        // The output records the hash that the next run compares against.
+        let (mut stream, _) = arrow_stream();
+        apply(&mut stream, &["--arrow-offset=10"], Projectile::Arrow);
+        insert_marker_hash(&mut stream, original);
+        assert_eq!(read_marker_tag(&stream).unwrap().hash, Some(original));
+    }
+
+    #[test]
+    fn templates_round_trip() {
+        let args = fixtures::args(&[
+            "--arrow-offset=10",
+            "--arrow-scale=0.5",
+            "--bolt-offset=5",
+            "--bolt-scale=0.75",
+            "--throwable-offset=2",
+        ]);
+
+        for projectile in [Projectile::Arrow, Projectile::Bolt, Projectile::Thrown] {
+            let params = args.params(projectile);
+            let (template, reason) = template_stream(&args, &params);
+            assert_eq!(reason, None);
+
+            let mut reloaded = NiStream::default();
+            reloaded
+                .load_bytes(&template.save_bytes().unwrap())
+                .unwrap();
+
+            let marker = read_marker_tag(&reloaded).unwrap();
+            assert!(marker.offset.abs_diff_eq(params.offset_vec(), 1e-6));
+            assert_eq!(marker.scale, params.scale);
+            assert_eq!(marker.projectile, Some(projectile));
+
+            let child = reloaded
+                .objects_of_type::<NiNode>()
+                .find(|node| node.name == TEMPLATE_NAME)
+                .unwrap();
+            assert!(child.translation.abs_diff_eq(params.offset_vec(), 1e-6));
+            assert_eq!(child.scale, params.scale);
+        }
+    }
+
+    #[test]
+    fn jitter_is_reproducible_per_type() {
+        let jittered = |args: &[&str], projectile| {
+            let (mut stream, arrow) = arrow_stream();
+            apply(&mut stream, args, projectile);
+            stream.get(arrow).unwrap().rotation
+        };
+        let args = ["--jitter-rotation=30", "--seed=7"];
+
+        for projectile in [Projectile::Arrow, Projectile::Bolt, Projectile::Thrown] {
+            assert_eq!(
+                jittered(&args, projectile),
+                jittered(&args, projectile),
+                "{projectile:?}"
+            );
+        }
+        let arrow = jittered(&args, Projectile::Arrow);
+        assert!(!arrow.abs_diff_eq(jittered(&args, Projectile::Bolt), 1e-4));
+        assert!(!arrow.abs_diff_eq(
+            jittered(&["--jitter-rotation=30", "--seed=8"], Projectile::Arrow),
+            1e-4
+        ));
+
+        // Each type can have a magnitude of its own.
+        let args = ["--arrow-jitter=30"];
+        assert_ne!(jittered(&args, Projectile::Arrow), Mat3::IDENTITY);
+        assert_eq!(jittered(&args, Projectile::Bolt), Mat3::IDENTITY);
+    }
+
+    #[test]
+    fn rotation_composes_in_local_space() {
+        let (mut stream, arrow) = arrow_stream();
+        let tip = fixtures::node(&mut stream, "ArrowTip", &[]);
+        stream
+            .get_as_mut::<_, NiNode>(arrow)
+            .unwrap()
+            .children
+            .push(tip);
+        stream.get_mut(tip).unwrap().translation = Vec3::new(0.0, 5.0, 0.0);
+        let source = Mat3::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        stream.get_mut(arrow).unwrap().rotation = source;
+        let args = [
+            "--arrow-offset=10",
+            "--arrow-scale=0.5",
+            "--jitter-rotation=30",
+            "--scale-pivot=center",
+            "--anchor-node=ArrowTip",
+        ];
+
+        apply(&mut stream, &args, Projectile::Arrow);
+
+        // The jitter turns the node about its own axes, before its rotation.
+        let jitter = read_marker_tag(&stream).unwrap().rotation.unwrap();
+        let object = stream.get(arrow).unwrap();
+        assert!(object.rotation.abs_diff_eq(source * jitter, 1e-6));
+        assert!((object.rotation.determinant() - 1.0).abs() < 1e-5);
+        assert_eq!(object.scale, 0.5);
+
+        // The anchor is placed with the composed rotation.
+        let offset = fixtures::args(&args).params(Projectile::Arrow).offset_vec();
+        let anchor = geometry::transform_to(&stream, arrow, tip).unwrap();
+        assert!(anchor.translation.abs_diff_eq(offset, 1e-4));
+
+        let once = stream.clone();
+        apply(&mut stream, &args, Projectile::Arrow);
+        assert_same_pose(&once, &stream, arrow);
+    }
+
+    #[test]
+    fn auto_scales_to_a_default_length() {
+        let mut stream = NiStream::default();
+        let shape = fixtures::tetrahedron(&mut stream, "Shape", Vec3::new(1.0, 120.0, 1.0));
+        let arrow = fixtures::node(&mut stream, "Arrow", &[shape]);
+        fixtures::root(&mut stream, &[arrow]);
+
+        // Neither offsets nor scales are required with --auto.
+        let args = Args::try_parse_from([clap::crate_name!(), "--auto"]).unwrap();
+        apply_pincushion(&mut stream, &args, &args.params(Projectile::Arrow), "test");
+
+        // Half the length, and sunk by a quarter of it.
+        let arrow = stream.get(arrow).unwrap();
+        assert!((arrow.scale - 0.5).abs() < 1e-6);
+        assert!(
+            arrow
+                .translation
+                .abs_diff_eq(Vec3::new(0.0, 15.0, 0.0), 1e-4)
+        );
+    }
+}
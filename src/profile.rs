@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::cli::{Params, Projectile};
+
+/// A named variant of the transform parameters, e.g. for soft or armored
+/// targets. Anything left unset falls back to the command line arguments.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Appended to the file stem of each generated variant.
+    pub suffix: String,
+    pub arrow_offset: Option<f32>,
+    pub arrow_scale: Option<f32>,
+    pub bolt_offset: Option<f32>,
+    pub bolt_scale: Option<f32>,
+    pub throwable_offset: Option<f32>,
+}
+
+/// Profiles keyed by name, kept sorted so output order is stable.
+pub type Profiles = BTreeMap<String, Profile>;
+
+impl Profile {
+    /// Override the parameters of the given projectile type.
+    pub fn apply(&self, params: &mut Params) {
+        let (offset, scale) = match params.projectile {
+            Projectile::Arrow => (self.arrow_offset, self.arrow_scale),
+            Projectile::Bolt => (self.bolt_offset, self.bolt_scale),
+            Projectile::Thrown => (self.throwable_offset, None),
+        };
+        if let Some(offset) = offset {
+            params.offset = offset;
+        }
+        if let Some(scale) = scale {
+            params.scale = scale;
+        }
+    }
+}
+
+/// Parse a profiles file of the form:
+///
+/// ```toml
+/// [armored]
+/// suffix = "_armored"
+/// arrow_offset = 4.0
+/// ```
+///
+pub fn parse_profiles(s: &str) -> Result<Profiles, String> {
+    let text = std::fs::read_to_string(Path::new(s)).map_err(|e| format!("{s:?}: {e}"))?;
+    let profiles: Profiles = toml::from_str(&text).map_err(|e| format!("{s:?}: {e}"))?;
+
+    // Variants would overwrite each other on disk.
+    let mut suffixes = std::collections::HashSet::new();
+    for (name, profile) in &profiles {
+        if !suffixes.insert(profile.suffix.to_lowercase()) {
+            return Err(format!(
+                "profile {name:?} reuses suffix {:?}",
+                profile.suffix
+            ));
+        }
+    }
+
+    Ok(profiles)
+}
@@ -0,0 +1,626 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// The outcome of processing a single projectile mesh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Saved,
+    /// Would have been saved, but this was a dry run.
+    WouldSave,
+    /// Saved, but nothing was transformed. See `MeshRecord::reason`.
+    Unprocessed,
+    Skipped,
+    NotFound,
+    /// The path resolved to a directory or other non-file entry.
+    NotAFile,
+    /// The path resolved to a file that isn't a NIF at all.
+    NotNif,
+    ParseFailed,
+    InvalidRootCount,
+    /// The output template rendered an illegal path.
+    InvalidOutputPath,
+    /// Saved, but the post-processing hook failed.
+    HookFailed,
+    /// The generated mesh could not be written.
+    SaveFailed,
+}
+
+/// Per-mesh results, shared by all of the report formats.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MeshRecord {
+    pub weapon_id: String,
+    pub weapon_type: String,
+    pub source_path: String,
+    pub output_path: String,
+    pub offset: f32,
+    pub scale: f32,
+    pub status: Status,
+    /// Whether the root had to be wrapped in a new node.
+    #[serde(default)]
+    pub wrap_root: bool,
+    /// Whether the source already had collision disabled by an NC tag.
+    #[serde(default)]
+    pub had_no_collision: bool,
+    /// The profile that produced this variant, empty without profiles. The
+    /// untouched copy written by `--stuck-suffix` is listed as "flying".
+    #[serde(default)]
+    pub profile: String,
+    /// Why a mesh was unprocessed, skipped or failed, e.g. "empty-root".
+    pub reason: String,
+    /// A hash of the weapon record fields the mesh was generated from, see
+    /// `--only-changed-records`.
+    #[serde(default)]
+    pub record_hash: String,
+    /// The bounds of the transformed geometry, see `--export-bounds`. Not
+    /// part of the manifest.
+    #[serde(skip)]
+    pub bounds: Option<Bounds>,
+    /// The weapon's mesh field as written in its plugin. Not part of the
+    /// manifest.
+    #[serde(skip)]
+    pub mesh: String,
+}
+
+/// An axis aligned bounding box in the space of the mesh's root.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub center: [f32; 3],
+    pub extents: [f32; 3],
+}
+
+impl Bounds {
+    pub fn new(min: [f32; 3], max: [f32; 3]) -> Self {
+        Self {
+            min,
+            max,
+            center: std::array::from_fn(|i| (min[i] + max[i]) / 2.0),
+            extents: std::array::from_fn(|i| max[i] - min[i]),
+        }
+    }
+}
+
+/// The bounds of a generated mesh, see `--export-bounds`.
+#[derive(Clone, Debug, Serialize)]
+pub struct BoundsEntry {
+    pub output_path: String,
+    #[serde(flatten)]
+    pub bounds: Bounds,
+}
+
+/// A projectile weapon and the mesh it uses, see `export-mapping`.
+#[derive(Clone, Debug, Serialize)]
+pub struct MappingEntry {
+    pub weapon_id: String,
+    pub weapon_type: String,
+    /// The path within the VFS, e.g. "meshes/w/w_arrow01.nif".
+    pub mesh_path: String,
+    /// Where the mesh was found on disk, if anywhere.
+    pub resolved_path: Option<String>,
+}
+
+/// A projectile mesh and every weapon using it, see `--shared-mesh-report`.
+#[derive(Clone, Debug, Serialize)]
+pub struct SharedMesh {
+    /// The path within the VFS, e.g. "meshes/w/w_arrow01.nif".
+    pub mesh_path: String,
+    pub weapon_ids: Vec<String>,
+    /// The distinct weapon types, more than one means they're transformed
+    /// differently.
+    pub weapon_types: Vec<String>,
+}
+
+/// Write the shared meshes as JSON for a .json path, or as a table otherwise.
+pub fn write_shared_meshes(path: &Path, entries: &[SharedMesh]) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+    {
+        serde_json::to_writer_pretty(file, entries)?;
+        return Ok(());
+    }
+
+    for entry in entries {
+        writeln!(
+            file,
+            "{} ({} weapons, {})",
+            entry.mesh_path,
+            entry.weapon_ids.len(),
+            entry.weapon_types.join(", ")
+        )?;
+        for id in &entry.weapon_ids {
+            writeln!(file, "    {id}")?;
+        }
+    }
+    file.flush()
+}
+
+/// A generated mesh and its thumbnail, see `--gallery`.
+#[derive(Clone, Debug)]
+pub struct GalleryEntry {
+    pub weapon_id: String,
+    pub profile: String,
+    /// The path relative to the output directory.
+    pub output_path: String,
+    /// The image path relative to the gallery, if rendering succeeded.
+    pub thumbnail: Option<String>,
+    pub offset: f32,
+    pub scale: f32,
+}
+
+/// Write the gallery entries as a single self-contained HTML page.
+pub fn write_gallery(path: &Path, entries: &[GalleryEntry]) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+
+    writeln!(file, "<!DOCTYPE html>")?;
+    writeln!(file, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(file, "<title>{} gallery</title>", env!("CARGO_PKG_NAME"))?;
+    writeln!(
+        file,
+        "<style>body{{font-family:sans-serif}}figure{{display:inline-block;\
+         width:256px;margin:8px;vertical-align:top}}img{{width:256px}}\
+         figcaption{{font-size:small;word-break:break-all}}</style>"
+    )?;
+    writeln!(file, "</head><body>")?;
+    writeln!(file, "<h1>{} meshes</h1>", entries.len())?;
+
+    for entry in entries {
+        writeln!(file, "<figure>")?;
+        match &entry.thumbnail {
+            Some(thumbnail) => writeln!(file, "<img src=\"{}\" alt=\"\">", escape(thumbnail))?,
+            None => writeln!(file, "<p>No thumbnail</p>")?,
+        }
+        let mut caption = escape(&entry.weapon_id);
+        if !entry.profile.is_empty() {
+            caption += &format!(" ({})", escape(&entry.profile));
+        }
+        writeln!(
+            file,
+            "<figcaption><b>{caption}</b><br>{}<br>offset {} scale {}</figcaption>",
+            escape(&entry.output_path),
+            entry.offset,
+            entry.scale
+        )?;
+        writeln!(file, "</figure>")?;
+    }
+
+    writeln!(file, "</body></html>")?;
+    file.flush()
+}
+
+/// Escape text for use in HTML content and quoted attributes.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A weapon record and the mesh it should point at, see `--record-report`.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordEntry {
+    pub weapon_id: String,
+    pub profile: String,
+    pub current_mesh: String,
+    pub suggested_mesh: String,
+}
+
+impl RecordEntry {
+    /// Print the entries as a table, for entering them by hand in OpenMW-CS.
+    pub fn print_table(entries: &[Self]) {
+        let headers = ["Weapon", "Profile", "Current mesh", "Suggested mesh"];
+        let rows: Vec<[&str; 4]> = entries
+            .iter()
+            .map(|e| {
+                [
+                    &*e.weapon_id,
+                    &*e.profile,
+                    &*e.current_mesh,
+                    &*e.suggested_mesh,
+                ]
+            })
+            .collect();
+
+        let mut widths = headers.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        for row in std::iter::once(&headers).chain(&rows) {
+            let [a, b, c, d] = row;
+            let [wa, wb, wc, _] = widths;
+            println!("{a:wa$}  {b:wb$}  {c:wc$}  {d}");
+        }
+    }
+}
+
+/// The results of `audit`, listing mesh paths by problem.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AuditReport {
+    /// The number of distinct projectile meshes.
+    pub projectiles: usize,
+    pub not_found: Vec<String>,
+    pub not_a_file: Vec<String>,
+    pub not_nif: Vec<String>,
+    pub parse_failed: Vec<String>,
+    pub invalid_root_count: Vec<String>,
+    /// Meshes that have collision disabled already, including our own.
+    pub no_collision: Vec<String>,
+}
+
+impl AuditReport {
+    pub fn print(&self) {
+        println!("Projectile meshes: {}", self.projectiles);
+        for (label, paths) in [
+            ("Not found", &self.not_found),
+            ("Not a file", &self.not_a_file),
+            ("Not a NIF", &self.not_nif),
+            ("Parse failed", &self.parse_failed),
+            ("Invalid root count", &self.invalid_root_count),
+            ("Collision disabled", &self.no_collision),
+        ] {
+            println!("{label}: {}", paths.len());
+            for path in paths {
+                println!("    {path}");
+            }
+        }
+    }
+}
+
+/// The differences between two manifests, see `diff-manifest`.
+#[derive(Clone, Debug, Default)]
+pub struct ManifestDiff {
+    pub added: Vec<MeshRecord>,
+    pub removed: Vec<MeshRecord>,
+    /// Pairs of old and new records whose transform, status or output changed.
+    pub changed: Vec<(MeshRecord, MeshRecord)>,
+}
+
+impl ManifestDiff {
+    /// Compare records by source path, weapon and profile, which stay the same
+    /// across runs even when the output path or status changes. The weapon
+    /// tells apart the outputs of a mesh split between several weapons.
+    pub fn new(old: &[MeshRecord], new: &[MeshRecord]) -> Self {
+        let id = |record: &MeshRecord| {
+            (
+                record.source_path.to_lowercase(),
+                record.weapon_id.to_lowercase(),
+                record.profile.clone(),
+            )
+        };
+
+        let old_map: BTreeMap<_, _> = old.iter().map(|record| (id(record), record)).collect();
+        let new_map: BTreeMap<_, _> = new.iter().map(|record| (id(record), record)).collect();
+
+        let mut diff = Self::default();
+
+        for (key, &record) in &new_map {
+            match old_map.get(key) {
+                None => diff.added.push(record.clone()),
+                Some(&previous) if previous.differs(record) => {
+                    diff.changed.push((previous.clone(), record.clone()));
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (key, &record) in &old_map {
+            if !new_map.contains_key(key) {
+                diff.removed.push(record.clone());
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn print(&self) {
+        println!("Added: {}", self.added.len());
+        for record in &self.added {
+            println!("    {}", record.describe());
+        }
+        println!("Removed: {}", self.removed.len());
+        for record in &self.removed {
+            println!("    {}", record.describe());
+        }
+        println!("Changed: {}", self.changed.len());
+        for (old, new) in &self.changed {
+            println!("    {}", old.source_path);
+            println!("        - {}", old.describe());
+            println!("        + {}", new.describe());
+        }
+    }
+}
+
+/// Totals for a whole run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessReport {
+    pub processed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Warnings logged during the run, only counted in strict mode. They're
+    /// not tied to any one mesh, so they're kept out of the summary line.
+    pub warnings: usize,
+}
+
+impl ProcessReport {
+    pub fn from_records(records: &[MeshRecord]) -> Self {
+        let mut report = Self::default();
+        for record in records {
+            match record.status {
+                Status::Saved | Status::WouldSave | Status::Unprocessed => report.processed += 1,
+                Status::Skipped => report.skipped += 1,
+                _ => report.failed += 1,
+            }
+        }
+        report
+    }
+
+    /// Like `from_records`, but also count unprocessed meshes as failures and
+    /// record the number of warnings, see `is_failure`.
+    pub fn from_records_strict(records: &[MeshRecord], warnings: usize) -> Self {
+        let mut report = Self::from_records(records);
+        let unprocessed = records
+            .iter()
+            .filter(|record| record.status == Status::Unprocessed)
+            .count();
+        report.processed -= unprocessed;
+        report.failed += unprocessed;
+        report.warnings = warnings;
+        report
+    }
+
+    /// Whether the run should exit with a failure status.
+    pub fn is_failure(&self) -> bool {
+        self.failed > 0 || self.warnings > 0
+    }
+
+    /// A single machine readable line, this format must be kept stable.
+    ///
+    /// `processed=<n> skipped=<n> failed=<n>`
+    ///
+    pub fn summary_line(&self) -> String {
+        format!(
+            "processed={} skipped={} failed={}",
+            self.processed, self.skipped, self.failed
+        )
+    }
+}
+
+impl MeshRecord {
+    /// Whether anything that affects the generated mesh differs.
+    fn differs(&self, other: &Self) -> bool {
+        self.status != other.status
+            || self.offset != other.offset
+            || self.scale != other.scale
+            || self.wrap_root != other.wrap_root
+            || !self.output_path.eq_ignore_ascii_case(&other.output_path)
+    }
+
+    /// A one line summary of the record for humans.
+    fn describe(&self) -> String {
+        let mut text = format!(
+            "{} [{:?}] offset={} scale={}",
+            self.key(),
+            self.status,
+            self.offset,
+            self.scale
+        );
+        if !self.reason.is_empty() {
+            text += &format!(" reason={}", self.reason);
+        }
+        text
+    }
+
+    /// Records are identified by their output, or their source if never saved.
+    pub fn key(&self) -> &str {
+        if self.output_path.is_empty() {
+            &self.source_path
+        } else {
+            &self.output_path
+        }
+    }
+}
+
+/// Manifest formats, chosen by the file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+    Toml,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let extension = path.extension().unwrap_or_default().to_ascii_lowercase();
+        match extension.to_str() {
+            Some("json") => Ok(Self::Json),
+            Some("csv") => Ok(Self::Csv),
+            Some("toml") => Ok(Self::Toml),
+            _ => Err(format!(
+                "unknown manifest format {path:?}, expected a .json, .csv or .toml file"
+            )),
+        }
+    }
+}
+
+/// TOML documents must be tables, so the records are nested under a key.
+#[derive(Serialize, Deserialize)]
+struct TomlManifest {
+    meshes: Vec<MeshRecord>,
+}
+
+/// Write the records in the format matching the path's extension.
+pub fn write_manifest(path: &Path, records: &[MeshRecord]) -> Result<(), String> {
+    match Format::from_path(path)? {
+        Format::Json => write_json(path, records).map_err(|e| e.to_string()),
+        Format::Csv => write_csv(path, records).map_err(|e| e.to_string()),
+        Format::Toml => {
+            let manifest = TomlManifest {
+                meshes: records.to_vec(),
+            };
+            let text = toml::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+            std::fs::write(path, text).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Read the records in the format matching the path's extension.
+pub fn read_manifest(path: &Path) -> Result<Vec<MeshRecord>, String> {
+    match Format::from_path(path)? {
+        Format::Json => read_json(path),
+        Format::Csv => {
+            let mut reader = csv::Reader::from_path(path).map_err(|e| e.to_string())?;
+            reader
+                .deserialize()
+                .collect::<Result<_, _>>()
+                .map_err(|e| e.to_string())
+        }
+        Format::Toml => {
+            let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let manifest: TomlManifest = toml::from_str(&text).map_err(|e| e.to_string())?;
+            Ok(manifest.meshes)
+        }
+    }
+}
+
+pub fn write_csv(path: &Path, records: &[MeshRecord]) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_record_csv(path: &Path, entries: &[RecordEntry]) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for entry in entries {
+        writer.serialize(entry)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_json(path: &Path, records: &[MeshRecord]) -> std::io::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(file, records)?;
+    Ok(())
+}
+
+pub fn write_mapping(path: &Path, entries: &[MappingEntry]) -> std::io::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(file, entries)?;
+    Ok(())
+}
+
+pub fn write_bounds(path: &Path, entries: &[BoundsEntry]) -> std::io::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(file, entries)?;
+    Ok(())
+}
+
+pub fn write_audit(path: &Path, report: &AuditReport) -> std::io::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(file, report)?;
+    Ok(())
+}
+
+pub fn read_json(path: &Path) -> Result<Vec<MeshRecord>, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| e.to_string())
+}
+
+/// Merge new records into existing ones, replacing entries with the same key.
+pub fn merge(mut existing: Vec<MeshRecord>, new: Vec<MeshRecord>) -> Vec<MeshRecord> {
+    for record in new {
+        match existing.iter_mut().find(|r| r.key() == record.key()) {
+            Some(entry) => *entry = record,
+            None => existing.push(record),
+        }
+    }
+    existing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(source_path: &str, status: Status) -> MeshRecord {
+        MeshRecord {
+            weapon_id: String::new(),
+            weapon_type: String::new(),
+            source_path: source_path.into(),
+            output_path: String::new(),
+            offset: 0.0,
+            scale: 1.0,
+            status,
+            wrap_root: false,
+            had_no_collision: false,
+            profile: String::new(),
+            reason: String::new(),
+            record_hash: String::new(),
+            bounds: None,
+            mesh: String::new(),
+        }
+    }
+
+    #[test]
+    fn strict_mode_counts_meshes_not_warnings() {
+        let records = [
+            record("a.nif", Status::Saved),
+            record("b.nif", Status::Unprocessed),
+            record("c.nif", Status::Skipped),
+        ];
+
+        let report = ProcessReport::from_records_strict(&records, 5);
+
+        assert_eq!(report.summary_line(), "processed=1 skipped=1 failed=1");
+        assert_eq!(report.warnings, 5);
+        assert!(report.is_failure());
+
+        let report = ProcessReport::from_records_strict(&records[..1], 1);
+        assert_eq!(report.summary_line(), "processed=1 skipped=0 failed=0");
+        assert!(report.is_failure());
+        assert!(!ProcessReport::from_records(&records[..1]).is_failure());
+    }
+
+    #[test]
+    fn dry_runs_count_as_processed() {
+        let records = [record("a.nif", Status::WouldSave)];
+        let report = ProcessReport::from_records(&records);
+        assert_eq!(report.summary_line(), "processed=1 skipped=0 failed=0");
+
+        let json = serde_json::to_string(&Status::WouldSave).unwrap();
+        assert_eq!(json, "\"would-save\"");
+    }
+
+    #[test]
+    fn split_meshes_are_compared_per_weapon() {
+        let split = |weapon_id: &str, offset: f32| MeshRecord {
+            weapon_id: weapon_id.into(),
+            offset,
+            ..record("meshes/w/shared.nif", Status::Saved)
+        };
+        let old = [split("Arrow", 1.0), split("Bolt", 2.0)];
+        let new = [split("Arrow", 1.0), split("Bolt", 3.0)];
+
+        let diff = ManifestDiff::new(&old, &new);
+
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].1.weapon_id, "Bolt");
+    }
+}
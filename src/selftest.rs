@@ -0,0 +1,141 @@
+use clap::{Parser, crate_name};
+
+use tes3::nif::*;
+
+use crate::cli::{Args, Projectile};
+use crate::error::PincushionError;
+use crate::pincushion::{
+    Marker, apply_pincushion, has_marker_tag, read_marker_tag, template_stream,
+};
+
+/// Offset and scale used for the synthetic arrow.
+const OFFSET: f32 = 10.0;
+const SCALE: f32 = 0.5;
+
+/// A minimal mesh, a root node with a single named child.
+fn build_stream() -> NiStream {
+    let mut stream = NiStream::default();
+
+    let mut child = NiNode::default();
+    child.name = "SelfTestArrow".into();
+    let child = stream.insert(child);
+
+    let mut root = NiNode::default();
+    root.children.push(child.cast());
+    let root = stream.insert(root);
+
+    stream.roots.push(root.cast());
+    stream
+}
+
+/// Check that the child of the root node has been transformed, and that the
+/// tags we rely on are present.
+fn verify(stream: &NiStream) -> Result<(), String> {
+    let root = stream
+        .roots
+        .first()
+        .and_then(|&root| stream.get_as::<_, NiNode>(root))
+        .ok_or("root is not a NiNode")?;
+
+    let child = root
+        .children
+        .first()
+        .and_then(|&child| stream.get(child))
+        .ok_or("root has no children")?;
+
+    let expected = Vec3::new(0.0, OFFSET, 0.0);
+    if !child.translation.abs_diff_eq(expected, 1e-6) {
+        return Err(format!(
+            "translation is {}, expected {expected}",
+            child.translation
+        ));
+    }
+    if (child.scale - SCALE).abs() > 1e-6 {
+        return Err(format!("scale is {}, expected {SCALE}", child.scale));
+    }
+
+    let has_nc_tag = stream
+        .objects_of_type::<NiStringExtraData>()
+        .any(|extra_data| extra_data.value == "NC");
+    if !has_nc_tag {
+        return Err("missing NC tag".into());
+    }
+    if !has_marker_tag(stream) {
+        return Err("missing marker tag".into());
+    }
+
+    Ok(())
+}
+
+fn run_steps() -> Result<(), String> {
+    // Parse fresh arguments so that flags given for the self test itself
+    // can't change the expected results.
+    let args = Args::try_parse_from([
+        crate_name!(),
+        &format!("--arrow-offset={OFFSET}"),
+        &format!("--arrow-scale={SCALE}"),
+        "--bolt-offset=0",
+        "--bolt-scale=1",
+    ])
+    .map_err(|e| e.to_string())?;
+
+    let params = args.params(Projectile::Arrow);
+
+    let mut stream = build_stream();
+    if let Some(reason) = apply_pincushion(&mut stream, &args, &params, "selftest") {
+        return Err(format!("nothing was transformed ({reason})"));
+    }
+    verify(&stream).map_err(|e| format!("transform: {e}"))?;
+
+    let bytes = stream.save_bytes().map_err(|e| format!("serialize: {e}"))?;
+
+    let mut reloaded = NiStream::default();
+    reloaded
+        .load_bytes(&bytes)
+        .map_err(|e| format!("parse: {e}"))?;
+    verify(&reloaded).map_err(|e| format!("round-trip: {e}"))?;
+
+    // A saved template must give back the transform it was made from.
+    let (template, reason) = template_stream(&args, &params);
+    if let Some(reason) = reason {
+        return Err(format!("template: nothing was transformed ({reason})"));
+    }
+    let bytes = template
+        .save_bytes()
+        .map_err(|e| format!("template serialize: {e}"))?;
+    let mut reloaded = NiStream::default();
+    reloaded
+        .load_bytes(&bytes)
+        .map_err(|e| format!("template parse: {e}"))?;
+    verify(&reloaded).map_err(|e| format!("template round-trip: {e}"))?;
+    match read_marker_tag(&reloaded) {
+        Some(Marker { offset, scale, .. })
+            if offset.abs_diff_eq(params.offset_vec(), 1e-6) && scale == params.scale => {}
+        Some(Marker { offset, scale, .. }) => {
+            return Err(format!(
+                "template round-trip: extracted offset={offset} scale={scale}, expected \
+                 offset={} scale={}",
+                params.offset_vec(),
+                params.scale
+            ));
+        }
+        None => return Err("template round-trip: missing marker tag".into()),
+    }
+
+    Ok(())
+}
+
+/// Run the pipeline on a synthetic mesh and print PASS or FAIL.
+///
+/// No game data is needed, so this confirms that a build works at all.
+///
+pub fn selftest() {
+    match run_steps() {
+        Ok(()) => println!("PASS"),
+        Err(reason) => {
+            let error = PincushionError::Verification { reason };
+            println!("FAIL: {error}");
+            std::process::exit(1);
+        }
+    }
+}